@@ -27,6 +27,9 @@ pub struct Sounds {
     pub stunned: Rc<geng::Sound>,
     pub teleport: Rc<geng::Sound>,
     pub throw_mushroom: Rc<geng::Sound>,
+    pub wall_built: Rc<geng::Sound>,
+    pub boulder_roll: Rc<geng::Sound>,
+    pub boulder_crumble: Rc<geng::Sound>,
 }
 
 #[derive(geng::asset::Load)]
@@ -42,6 +45,8 @@ pub struct Sprites {
     pub tiles: Vec<PixelTexture>,
     pub highlight_tile: PixelTexture,
     pub wall: PixelTexture,
+    pub terrain: TerrainSprites,
+    pub hazards: HazardSprites,
     pub mushroom: PixelTexture,
     pub base: PixelTexture,
     pub characters: CharacterSprites,
@@ -91,6 +96,20 @@ pub struct CharacterSprites {
     pub unicorn: PixelTexture,
 }
 
+#[derive(geng::asset::Load)]
+pub struct TerrainSprites {
+    pub mud: PixelTexture,
+    pub ice: PixelTexture,
+    pub water: PixelTexture,
+    pub bush: PixelTexture,
+}
+
+#[derive(geng::asset::Load)]
+pub struct HazardSprites {
+    pub boulder: PixelTexture,
+    pub beetle: PixelTexture,
+}
+
 #[derive(geng::asset::Load)]
 pub struct AbilitySprites {
     pub sprint: PixelTexture,
@@ -99,6 +118,8 @@ pub struct AbilitySprites {
     pub teleport_disable: PixelTexture,
     pub throw: PixelTexture,
     pub throw_disable: PixelTexture,
+    pub wall: PixelTexture,
+    pub wall_disable: PixelTexture,
 }
 
 #[derive(geng::asset::Load)]
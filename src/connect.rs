@@ -0,0 +1,372 @@
+use crate::{
+    assets::Assets,
+    game::Game,
+    interop::*,
+    model::PlayerCustomization,
+    settings::Settings,
+    ui::{UiContext, WidgetSfxConfig, WidgetState},
+};
+
+use std::{cell::RefCell, pin::Pin};
+
+use geng::prelude::*;
+use geng_utils::conversions::*;
+
+/// Server status shown in the server list.
+#[derive(Debug, Clone)]
+pub struct ServerInfo {
+    pub status: ServerStatus,
+    /// Round trip time of the status query in seconds.
+    pub ping: f32,
+}
+
+/// Asks the server for its status without joining the game.
+pub async fn query_status(address: String) -> anyhow::Result<ServerInfo> {
+    let mut connection: ClientConnection = geng::net::client::connect(&address).await?;
+    let timer = Timer::new();
+    connection.send(ClientMessage::QueryStatus);
+    let message = connection
+        .next()
+        .await
+        .ok_or_else(|| anyhow::anyhow!("connection closed"))??;
+    let ServerMessage::Status(status) = message else {
+        anyhow::bail!("unexpected message from the server");
+    };
+    Ok(ServerInfo {
+        status,
+        ping: timer.elapsed().as_secs_f64() as f32,
+    })
+}
+
+/// A server in the server list.
+pub struct ServerEntry {
+    pub address: String,
+    pub favorite: bool,
+    pub status: ServerEntryStatus,
+}
+
+pub enum ServerEntryStatus {
+    Querying(Pin<Box<dyn Future<Output = anyhow::Result<ServerInfo>>>>),
+    Online(ServerInfo),
+    Offline,
+}
+
+impl ServerEntry {
+    pub fn new(address: String, favorite: bool) -> Self {
+        let mut entry = Self {
+            address,
+            favorite,
+            status: ServerEntryStatus::Offline,
+        };
+        entry.refresh();
+        entry
+    }
+
+    /// Starts a new status query.
+    pub fn refresh(&mut self) {
+        self.status = ServerEntryStatus::Querying(Box::pin(query_status(self.address.clone())));
+    }
+
+    /// Checks whether the status query has finished.
+    pub fn update(&mut self) {
+        if let ServerEntryStatus::Querying(query) = &mut self.status
+            && let Some(result) = query.now_or_never()
+        {
+            self.status = match result {
+                Ok(info) => ServerEntryStatus::Online(info),
+                Err(err) => {
+                    log::debug!("server {} is offline: {err:?}", self.address);
+                    ServerEntryStatus::Offline
+                }
+            };
+        }
+    }
+}
+
+/// What to do once connected to the server.
+#[derive(Debug, Clone)]
+pub struct Join {
+    pub address: String,
+    /// Customization to play with, or `None` to spectate.
+    pub customization: Option<PlayerCustomization>,
+}
+
+/// Connects to the server and starts the game, or shows the error if it fails.
+pub fn connect(
+    geng: &Geng,
+    assets: &Rc<Assets>,
+    settings: &Rc<RefCell<Settings>>,
+    join: Join,
+) -> impl geng::State {
+    let future = {
+        let geng = geng.clone();
+        let assets = assets.clone();
+        let settings = settings.clone();
+        async move {
+            let result = async {
+                let connection = geng::net::client::connect(&join.address).await?;
+                match join.customization.clone() {
+                    Some(customization) => {
+                        Game::new(
+                            &geng,
+                            &assets,
+                            &settings,
+                            connection,
+                            join.address.clone(),
+                            customization,
+                        )
+                        .await
+                    }
+                    None => {
+                        Game::new_spectator(
+                            &geng,
+                            &assets,
+                            &settings,
+                            connection,
+                            join.address.clone(),
+                        )
+                        .await
+                    }
+                }
+            }
+            .await;
+            let state: Box<dyn geng::State> = match result {
+                Ok(game) => Box::new(game),
+                Err(err) => Box::new(ConnectionError::new(
+                    &geng,
+                    &assets,
+                    &settings,
+                    join,
+                    format!("{err}"),
+                )),
+            };
+            state
+        }
+    };
+    geng::LoadingScreen::new(geng, geng::EmptyLoadingScreen::new(geng), future)
+}
+
+/// Shown when the connection to the server fails or is lost, offers to retry.
+pub struct ConnectionError {
+    geng: Geng,
+    assets: Rc<Assets>,
+    settings: Rc<RefCell<Settings>>,
+    join: Join,
+    title: String,
+    error: String,
+    transition: Option<geng::state::Transition>,
+    unit_quad: ugli::VertexBuffer<draw2d::TexturedVertex>,
+    ui_context: UiContext,
+    ui: ConnectionErrorUi,
+    framebuffer_size: vec2<usize>,
+    post_texture: ugli::Texture,
+    time: f32,
+    active_touch: Option<u64>,
+}
+
+pub struct ConnectionErrorUi {
+    pub title: WidgetState,
+    pub error: WidgetState,
+    pub retry: WidgetState,
+    pub back: WidgetState,
+}
+
+impl ConnectionError {
+    pub fn new(
+        geng: &Geng,
+        assets: &Rc<Assets>,
+        settings: &Rc<RefCell<Settings>>,
+        join: Join,
+        error: String,
+    ) -> Self {
+        log::error!("failed to connect to {}: {error}", join.address);
+        let title = format!("Failed to connect to {}", join.address);
+        Self::with_title(geng, assets, settings, join, title, error)
+    }
+
+    /// The connection broke during the game.
+    pub fn lost(
+        geng: &Geng,
+        assets: &Rc<Assets>,
+        settings: &Rc<RefCell<Settings>>,
+        join: Join,
+        error: String,
+    ) -> Self {
+        log::error!("lost connection to {}: {error}", join.address);
+        let title = format!("Lost connection to {}", join.address);
+        Self::with_title(geng, assets, settings, join, title, error)
+    }
+
+    fn with_title(
+        geng: &Geng,
+        assets: &Rc<Assets>,
+        settings: &Rc<RefCell<Settings>>,
+        join: Join,
+        title: String,
+        error: String,
+    ) -> Self {
+        Self {
+            geng: geng.clone(),
+            assets: assets.clone(),
+            settings: settings.clone(),
+            join,
+            title,
+            error,
+            transition: None,
+            unit_quad: geng_utils::geometry::unit_quad_geometry(geng.ugli()),
+            ui_context: UiContext::new(geng, assets),
+            ui: ConnectionErrorUi::new(geng, assets),
+            framebuffer_size: vec2(1, 1),
+            post_texture: geng_utils::texture::new_texture(geng.ugli(), vec2(1, 1)),
+            time: 0.0,
+            active_touch: None,
+        }
+    }
+}
+
+impl geng::State for ConnectionError {
+    fn transition(&mut self) -> Option<geng::state::Transition> {
+        self.transition.take()
+    }
+
+    fn update(&mut self, delta_time: f64) {
+        self.time += delta_time as f32;
+        self.ui_context.apply_settings(&self.settings.borrow());
+        self.ui_context
+            .update(delta_time as f32, self.active_touch.is_some());
+        self.ui.update(&mut self.ui_context, self.framebuffer_size);
+
+        if self.ui.retry.mouse_left.clicked {
+            let state = connect(&self.geng, &self.assets, &self.settings, self.join.clone());
+            self.transition = Some(geng::state::Transition::Switch(Box::new(state)));
+        }
+        if self.ui.back.mouse_left.clicked {
+            self.transition = Some(geng::state::Transition::Pop);
+        }
+    }
+
+    fn handle_event(&mut self, event: geng::Event) {
+        match event {
+            geng::Event::KeyPress {
+                key: geng::Key::Escape,
+            } => {
+                self.transition = Some(geng::state::Transition::Pop);
+            }
+            geng::Event::CursorMove { position } => {
+                self.ui_context.cursor.cursor_move(position.as_f32());
+            }
+            geng::Event::TouchStart(touch) if self.active_touch.is_none() => {
+                self.active_touch = Some(touch.id);
+                self.ui_context.cursor.cursor_move(touch.position.as_f32());
+            }
+            geng::Event::TouchMove(touch) if Some(touch.id) == self.active_touch => {
+                self.ui_context.cursor.cursor_move(touch.position.as_f32());
+            }
+            geng::Event::TouchEnd(touch) if Some(touch.id) == self.active_touch => {
+                self.ui_context.cursor.cursor_move(touch.position.as_f32());
+                self.active_touch = None;
+            }
+            _ => (),
+        }
+    }
+
+    fn draw(&mut self, final_buffer: &mut ugli::Framebuffer) {
+        self.framebuffer_size = final_buffer.size();
+        geng_utils::texture::update_texture_size(
+            &mut self.post_texture,
+            final_buffer.size(),
+            self.geng.ugli(),
+        );
+        let framebuffer =
+            &mut geng_utils::texture::attach_texture(&mut self.post_texture, self.geng.ugli());
+        ugli::clear(
+            framebuffer,
+            Some(Rgba::try_from("#1A151F").unwrap()),
+            None,
+            None,
+        );
+
+        let text_color = |state: &WidgetState| {
+            if state.hovered {
+                Rgba::try_from("#5590B4").unwrap()
+            } else {
+                Rgba::try_from("#B4A091").unwrap()
+            }
+        };
+        for (text, color, widget) in [
+            (
+                self.title.clone(),
+                Rgba::try_from("#B03B59").unwrap(),
+                &self.ui.title,
+            ),
+            (
+                self.error.clone(),
+                Rgba::try_from("#474C80").unwrap(),
+                &self.ui.error,
+            ),
+            (
+                "Retry".to_owned(),
+                text_color(&self.ui.retry),
+                &self.ui.retry,
+            ),
+            ("Back".to_owned(), text_color(&self.ui.back), &self.ui.back),
+        ] {
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &draw2d::Text::unit(self.assets.font.clone(), text, color)
+                    .fit_into(widget.position),
+            );
+        }
+
+        self.ui_context.frame_end();
+
+        crate::render::draw_crt(
+            &self.geng,
+            &self.assets,
+            &self.unit_quad,
+            &self.post_texture,
+            self.settings.borrow().crt_strength,
+            self.time,
+            final_buffer,
+        );
+    }
+}
+
+impl ConnectionErrorUi {
+    pub fn new(_geng: &Geng, _assets: &Rc<Assets>) -> Self {
+        Self {
+            title: WidgetState::new(),
+            error: WidgetState::new(),
+            retry: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+            back: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+        }
+    }
+
+    pub fn update(&mut self, context: &mut UiContext, framebuffer_size: vec2<usize>) {
+        let screen = Aabb2::ZERO.extend_positive(framebuffer_size.as_f32());
+        context.screen = screen;
+
+        let row_height = screen.height() * 0.05 * context.scale;
+        let width = (row_height * 16.0).min(screen.width() * 0.9);
+        let row = |i: f32| {
+            Aabb2::point(screen.center() - vec2(0.0, row_height * i))
+                .extend_symmetric(vec2(width, row_height) / 2.0)
+        };
+
+        self.title.update(row(-1.5), context);
+        self.error
+            .update(row(-0.5).extend_uniform(-row_height * 0.2), context);
+        let button_size = vec2(width * 0.25, row_height * 0.8);
+        self.retry.update(
+            Aabb2::point(row(1.5).center() - vec2(width * 0.2, 0.0))
+                .extend_symmetric(button_size / 2.0),
+            context,
+        );
+        self.back.update(
+            Aabb2::point(row(1.5).center() + vec2(width * 0.2, 0.0))
+                .extend_symmetric(button_size / 2.0),
+            context,
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use geng::prelude::*;
+
+/// Actions that can be bound to keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    Undo,
+    Confirm,
+    /// Use the ability in the loadout slot.
+    Ability(usize),
+}
+
+impl Action {
+    pub fn all() -> Vec<Self> {
+        let mut actions = vec![
+            Self::Up,
+            Self::Down,
+            Self::Left,
+            Self::Right,
+            Self::Undo,
+            Self::Confirm,
+        ];
+        actions.extend((0..crate::model::LOADOUT_SIZE).map(Self::Ability));
+        actions
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Self::Up => "Up".into(),
+            Self::Down => "Down".into(),
+            Self::Left => "Left".into(),
+            Self::Right => "Right".into(),
+            Self::Undo => "Undo".into(),
+            Self::Confirm => "Confirm".into(),
+            Self::Ability(slot) => format!("Ability {}", slot + 1),
+        }
+    }
+
+    /// Direction of the movement actions.
+    pub fn direction(&self) -> Option<vec2<i64>> {
+        match self {
+            Self::Up => Some(vec2(0, 1)),
+            Self::Down => Some(vec2(0, -1)),
+            Self::Left => Some(vec2(-1, 0)),
+            Self::Right => Some(vec2(1, 0)),
+            _ => None,
+        }
+    }
+}
+
+/// Gamepad buttons that can be bound to actions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    LeftBumper,
+    RightBumper,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+}
+
+impl GamepadButton {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::South => "A",
+            Self::East => "B",
+            Self::North => "Y",
+            Self::West => "X",
+            Self::DPadUp => "D-pad up",
+            Self::DPadDown => "D-pad down",
+            Self::DPadLeft => "D-pad left",
+            Self::DPadRight => "D-pad right",
+            Self::LeftBumper => "LB",
+            Self::RightBumper => "RB",
+            Self::LeftTrigger => "LT",
+            Self::RightTrigger => "RT",
+            Self::Select => "Select",
+            Self::Start => "Start",
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn from_gilrs(button: gilrs::Button) -> Option<Self> {
+        use gilrs::Button;
+        let button = match button {
+            Button::South => Self::South,
+            Button::East => Self::East,
+            Button::North => Self::North,
+            Button::West => Self::West,
+            Button::DPadUp => Self::DPadUp,
+            Button::DPadDown => Self::DPadDown,
+            Button::DPadLeft => Self::DPadLeft,
+            Button::DPadRight => Self::DPadRight,
+            Button::LeftTrigger => Self::LeftBumper,
+            Button::RightTrigger => Self::RightBumper,
+            Button::LeftTrigger2 => Self::LeftTrigger,
+            Button::RightTrigger2 => Self::RightTrigger,
+            Button::Select => Self::Select,
+            Button::Start => Self::Start,
+            _ => return None,
+        };
+        Some(button)
+    }
+}
+
+/// Rebindable keyboard and gamepad controls.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Controls {
+    pub bindings: Vec<(Action, Vec<geng::Key>)>,
+    pub gamepad: Vec<(Action, Vec<GamepadButton>)>,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        use geng::Key;
+        Self {
+            bindings: vec![
+                (Action::Up, vec![Key::ArrowUp, Key::W]),
+                (Action::Down, vec![Key::ArrowDown, Key::S]),
+                (Action::Left, vec![Key::ArrowLeft, Key::A]),
+                (Action::Right, vec![Key::ArrowRight, Key::D]),
+                (Action::Undo, vec![Key::Backspace]),
+                (Action::Confirm, vec![Key::Enter]),
+                (Action::Ability(0), vec![Key::Digit1]),
+                (Action::Ability(1), vec![Key::Digit2]),
+                (Action::Ability(2), vec![Key::Digit3]),
+            ],
+            gamepad: vec![
+                (Action::Up, vec![GamepadButton::DPadUp]),
+                (Action::Down, vec![GamepadButton::DPadDown]),
+                (Action::Left, vec![GamepadButton::DPadLeft]),
+                (Action::Right, vec![GamepadButton::DPadRight]),
+                (Action::Undo, vec![GamepadButton::East]),
+                (Action::Confirm, vec![GamepadButton::South]),
+                (Action::Ability(0), vec![GamepadButton::West]),
+                (Action::Ability(1), vec![GamepadButton::North]),
+                (Action::Ability(2), vec![GamepadButton::RightBumper]),
+            ],
+        }
+    }
+}
+
+impl Controls {
+    /// The action bound to the key.
+    pub fn action(&self, key: geng::Key) -> Option<Action> {
+        bound_action(&self.bindings, &key)
+    }
+
+    pub fn keys(&self, action: Action) -> &[geng::Key] {
+        bound_inputs(&self.bindings, action)
+    }
+
+    /// Binds the key to the action, replacing its previous binding.
+    pub fn rebind(&mut self, action: Action, key: geng::Key) {
+        rebind(&mut self.bindings, action, key);
+    }
+
+    /// The action bound to the gamepad button.
+    pub fn gamepad_action(&self, button: GamepadButton) -> Option<Action> {
+        bound_action(&self.gamepad, &button)
+    }
+
+    pub fn gamepad_buttons(&self, action: Action) -> &[GamepadButton] {
+        bound_inputs(&self.gamepad, action)
+    }
+
+    /// Binds the gamepad button to the action, replacing its previous binding.
+    pub fn rebind_gamepad(&mut self, action: Action, button: GamepadButton) {
+        rebind(&mut self.gamepad, action, button);
+    }
+}
+
+fn bound_action<T: PartialEq>(bindings: &[(Action, Vec<T>)], input: &T) -> Option<Action> {
+    bindings
+        .iter()
+        .find(|(_, inputs)| inputs.contains(input))
+        .map(|&(action, _)| action)
+}
+
+fn bound_inputs<T>(bindings: &[(Action, Vec<T>)], action: Action) -> &[T] {
+    bindings
+        .iter()
+        .find(|(bound, _)| *bound == action)
+        .map_or(&[], |(_, inputs)| inputs.as_slice())
+}
+
+fn rebind<T: PartialEq>(bindings: &mut Vec<(Action, Vec<T>)>, action: Action, input: T) {
+    for (_, inputs) in bindings.iter_mut() {
+        inputs.retain(|bound| *bound != input);
+    }
+    match bindings.iter_mut().find(|(bound, _)| *bound == action) {
+        Some((_, inputs)) => *inputs = vec![input],
+        None => bindings.push((action, vec![input])),
+    }
+}
+
+/// Buttons pressed on the connected gamepads, gamepads are not supported on the web.
+pub struct Gamepads {
+    #[cfg(not(target_arch = "wasm32"))]
+    gilrs: Option<gilrs::Gilrs>,
+    /// Whether the presses queued up until the next poll belong to another state.
+    discard_queued: bool,
+}
+
+impl Default for Gamepads {
+    fn default() -> Self {
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            gilrs: gilrs::Gilrs::new()
+                .inspect_err(|err| log::warn!("gamepads are unavailable: {err}"))
+                .ok(),
+            discard_queued: false,
+        }
+    }
+}
+
+impl Gamepads {
+    /// Ignores the buttons pressed until the next poll,
+    /// for when another state takes over the input.
+    pub fn discard_queued(&mut self) {
+        self.discard_queued = true;
+    }
+
+    /// The buttons pressed since the last poll.
+    pub fn pressed(&mut self) -> Vec<GamepadButton> {
+        let mut pressed = Vec::new();
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(gilrs) = &mut self.gilrs {
+            while let Some(event) = gilrs.next_event() {
+                if let gilrs::EventType::ButtonPressed(button, _) = event.event
+                    && let Some(button) = GamepadButton::from_gilrs(button)
+                {
+                    pressed.push(button);
+                }
+            }
+        }
+        if std::mem::take(&mut self.discard_queued) {
+            pressed.clear();
+        }
+        pressed
+    }
+}
+
+/// Short name of the key to show to the player.
+pub fn key_name(key: geng::Key) -> String {
+    let name = format!("{key:?}");
+    ["Digit", "Arrow"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(&name)
+        .to_owned()
+}
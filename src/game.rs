@@ -1,21 +1,33 @@
 use crate::{
     assets::*,
+    connect::{ConnectionError, Join},
+    controls::{Action, Gamepads},
     interop::*,
     model::{
         shared::{GameEvent, Phase},
         *,
     },
     render::GameRender,
+    settings::{Profile, Settings, SettingsMenu},
     ui::{UiContext, WidgetSfxConfig, WidgetState},
 };
 
-use geng::prelude::*;
+use std::cell::RefCell;
+
+use geng::prelude::{itertools::Itertools, *};
 use geng_utils::conversions::*;
 
+/// Rows shown in the turn history panel at once.
+const HISTORY_ROWS: usize = 8;
+
 pub struct Game {
     connection: ClientConnection,
+    /// Address of the server, to reconnect to if the connection is lost.
+    address: String,
     geng: Geng,
     assets: Rc<Assets>,
+    settings: Rc<RefCell<Settings>>,
+    transition: Option<geng::state::Transition>,
     unit_quad: ugli::VertexBuffer<draw2d::TexturedVertex>,
     post_texture: ugli::Texture,
     ui_context: UiContext,
@@ -28,6 +40,8 @@ pub struct Game {
     active_touch: Option<u64>,
     cursor_pos: Option<CursorPos>,
     drag: Option<Drag>,
+    pinch: Option<Pinch>,
+    gamepads: Gamepads,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -38,59 +52,114 @@ pub struct CursorPos {
 }
 
 pub struct GameUi {
-    pub ability_sprint: WidgetState,
-    pub ability_teleport: WidgetState,
-    pub ability_throw: WidgetState,
+    /// Ability buttons in the loadout order.
+    pub abilities: Vec<(Ability, WidgetState)>,
     pub mushrooms: WidgetState,
+    /// Spectator sidebar rows for every player.
+    pub players: Vec<(ClientId, WidgetState)>,
+    /// Spectator toggle for showing the planned moves.
+    pub show_moves: WidgetState,
+    /// Spectator button to join the match as a player.
+    pub join: WidgetState,
+    pub minimap: WidgetState,
+    /// Button opening the turn history panel.
+    pub history_toggle: WidgetState,
+    pub history_open: bool,
+    pub history: WidgetState,
+    pub history_rows: Vec<WidgetState>,
+    /// Index of the first history line shown.
+    pub history_scroll: usize,
 }
 
 pub struct Drag {
     pub target: DragTarget,
 }
 
+/// Two touches zooming and panning the camera.
+pub struct Pinch {
+    /// Touch ids and their last positions on the screen.
+    pub touches: [(u64, vec2<f64>); 2],
+}
+
 pub enum DragTarget {
-    Player { path: Vec<vec2<ICoord>> },
+    Player {
+        path: Vec<vec2<ICoord>>,
+    },
+    /// Camera pan, remembers the last cursor position on the screen.
+    Camera {
+        last: vec2<f64>,
+    },
 }
 
 impl Game {
     pub async fn new(
         geng: &Geng,
         assets: &Rc<Assets>,
+        settings: &Rc<RefCell<Settings>>,
         mut connection: ClientConnection,
+        address: String,
         customization: PlayerCustomization,
-    ) -> Self {
+    ) -> anyhow::Result<Self> {
         connection.send(ClientMessage::SetCustomization(customization.clone()));
-        let ServerMessage::Setup(setup) = connection.next().await.unwrap().unwrap() else {
-            unreachable!()
+        let message = connection
+            .next()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("connection closed"))??;
+        let ServerMessage::Setup(setup) = message else {
+            anyhow::bail!("unexpected message from the server");
         };
 
-        Self {
+        // Without room on the map the player waits for the next match as a spectator
+        let mut model = client::ClientModel::new(setup.player_id, setup.model);
+        if !model.shared.players.contains_key(&model.player_id) {
+            model.spectating = true;
+            model.joining = true;
+        }
+
+        Ok(Self {
             connection,
+            address,
             geng: geng.clone(),
             assets: assets.clone(),
+            settings: settings.clone(),
+            transition: None,
             ui_context: UiContext::new(geng, assets),
             unit_quad: geng_utils::geometry::unit_quad_geometry(geng.ugli()),
             post_texture: geng_utils::texture::new_texture(geng.ugli(), vec2(1, 1)),
             render: GameRender::new(geng, assets),
-            model: client::ClientModel::new(setup.player_id, setup.model),
-            ui: GameUi::new(geng, assets),
+            model,
+            ui: GameUi::new(geng, assets, &customization.loadout),
             time: FTime::ZERO,
 
             framebuffer_size: vec2(1, 1),
             active_touch: None,
             cursor_pos: None,
             drag: None,
-        }
+            pinch: None,
+            gamepads: Gamepads::default(),
+        })
     }
 
     pub async fn new_spectator(
         geng: &Geng,
         assets: &Rc<Assets>,
+        settings: &Rc<RefCell<Settings>>,
         connection: ClientConnection,
-    ) -> Self {
-        let mut model = Self::new(geng, assets, connection, PlayerCustomization::random()).await;
+        address: String,
+    ) -> anyhow::Result<Self> {
+        let mut model = Self::new(
+            geng,
+            assets,
+            settings,
+            connection,
+            address,
+            PlayerCustomization::random(),
+        )
+        .await?;
         model.connection.send(ClientMessage::Spectate);
-        model
+        model.model.spectating = true;
+        model.model.joining = false;
+        Ok(model)
     }
 
     fn handle_event(&mut self, event: geng::Event) {
@@ -101,25 +170,53 @@ impl Game {
             geng::Event::MouseRelease {
                 button: geng::MouseButton::Left,
             } => self.mouse_release(),
-            geng::Event::CursorMove { position } => self.cursor_move(position),
-            geng::Event::KeyPress { key } => match key {
-                geng::Key::Digit1 => {
-                    let mut sfx = self.assets.sounds.click.play();
-                    sfx.set_volume(0.5);
-                    self.ability_sprint();
-                }
-                geng::Key::Digit2 => {
-                    let mut sfx = self.assets.sounds.click.play();
-                    sfx.set_volume(0.5);
-                    self.ability_teleport();
+            geng::Event::MousePress {
+                button: geng::MouseButton::Right | geng::MouseButton::Middle,
+            } => self.start_pan(),
+            geng::Event::MouseRelease {
+                button: geng::MouseButton::Right | geng::MouseButton::Middle,
+            } => {
+                if let Some(Drag {
+                    target: DragTarget::Camera { .. },
+                }) = self.drag
+                {
+                    self.drag = None;
                 }
-                geng::Key::Digit3 => {
-                    let mut sfx = self.assets.sounds.click.play();
-                    sfx.set_volume(0.5);
-                    self.ability_throw();
+            }
+            geng::Event::CursorMove { position } => self.cursor_move(position),
+            geng::Event::Wheel { delta } => self.ui_context.cursor.scroll += delta as f32,
+            geng::Event::KeyPress {
+                key: geng::Key::Escape,
+            } => {
+                let state = SettingsMenu::new(&self.geng, &self.assets, &self.settings);
+                self.transition = Some(geng::state::Transition::Push(Box::new(state)));
+                // The settings menu reads the gamepads while it is open
+                self.gamepads.discard_queued();
+            }
+            geng::Event::KeyPress { key } => {
+                let action = self.settings.borrow().controls.action(key);
+                if let Some(action) = action {
+                    self.handle_action(action);
                 }
-                _ => {}
-            },
+            }
+            geng::Event::TouchStart(touch)
+                if self.active_touch.is_some() && self.pinch.is_none() =>
+            {
+                self.start_pinch(touch.id, touch.position);
+            }
+            geng::Event::TouchMove(touch) if self.pinch.is_some() => {
+                self.pinch_move(touch.id, touch.position);
+            }
+            geng::Event::TouchEnd(touch)
+                if self
+                    .pinch
+                    .as_ref()
+                    .is_some_and(|pinch| pinch.touches.iter().any(|&(id, _)| id == touch.id)) =>
+            {
+                self.pinch = None;
+                self.cursor_pos = None;
+                self.active_touch = None;
+            }
             geng::Event::TouchStart(touch) if self.active_touch.is_none() => {
                 self.active_touch = Some(touch.id);
                 self.cursor_move(touch.position);
@@ -138,10 +235,169 @@ impl Game {
         }
     }
 
+    fn handle_action(&mut self, action: Action) {
+        match action {
+            Action::Ability(slot) => {
+                if let Some(&(ability, _)) = self.ui.abilities.get(slot) {
+                    let mut sfx = self.assets.sounds.click.play();
+                    sfx.set_volume(self.settings.borrow().volume());
+                    self.use_ability(ability);
+                }
+            }
+            Action::Undo => self.undo_move(),
+            Action::Confirm => self.confirm_move(),
+            Action::Up | Action::Down | Action::Left | Action::Right => {
+                if let Some(direction) = action.direction() {
+                    self.step_move(direction);
+                }
+            }
+        }
+    }
+
+    /// Extends the path, aims, or moves the teleport cursor by a step in the direction.
+    fn step_move(&mut self, direction: vec2<ICoord>) {
+        let shared = &self.model.shared;
+        let Some(player) = shared.players.get(&self.model.player_id) else {
+            return;
+        };
+        if !matches!(shared.phase, Phase::Planning { .. }) || player.stunned_duration.is_some() {
+            return;
+        }
+
+        if player.is_channeling {
+            // Move the teleport cursor
+            let start = self
+                .model
+                .key_cursor
+                .unwrap_or(match player.submitted_move {
+                    PlayerMove::TeleportActivate { teleport_to } => teleport_to,
+                    _ => player.pos,
+                });
+            let cursor = start + direction;
+            if shared::distance(player.pos, cursor) <= player.stats.teleport_range
+                && shared.map.is_in_bounds(cursor)
+            {
+                self.model.key_cursor = Some(cursor);
+            }
+            return;
+        }
+
+        match player.submitted_move.clone() {
+            PlayerMove::Normal { mut path, .. } => {
+                if path.is_empty() {
+                    path.push(player.pos);
+                }
+                let Some(&last) = path.last() else {
+                    return;
+                };
+                if shared.step_path(self.model.player_id, &mut path, last + direction) {
+                    self.submit_path(path);
+                }
+            }
+            PlayerMove::Throw { .. } | PlayerMove::BuildWall { .. } => {
+                let Some(player) = self.model.shared.players.get_mut(&self.model.player_id) else {
+                    return;
+                };
+                if let PlayerMove::Throw { direction: aim }
+                | PlayerMove::BuildWall { direction: aim } = &mut player.submitted_move
+                {
+                    *aim = direction;
+                }
+                self.connection
+                    .send(ClientMessage::SubmitMove(player.submitted_move.clone()));
+            }
+            _ => {}
+        }
+    }
+
+    /// Removes the last step of the path or cancels the planned ability.
+    fn undo_move(&mut self) {
+        if self.model.key_cursor.take().is_some() {
+            return;
+        }
+        let Some(player) = self.model.shared.players.get(&self.model.player_id) else {
+            return;
+        };
+        if !matches!(self.model.shared.phase, Phase::Planning { .. }) {
+            return;
+        }
+
+        match player.submitted_move.clone() {
+            PlayerMove::Normal { mut path, .. } => {
+                if path.len() > 1 {
+                    self.model.shared.undo_step(&mut path);
+                    self.submit_path(path);
+                }
+            }
+            _ => {
+                let Some(player) = self.model.shared.players.get_mut(&self.model.player_id) else {
+                    return;
+                };
+                player.submitted_move = PlayerMove::default();
+                self.connection
+                    .send(ClientMessage::SubmitMove(player.submitted_move.clone()));
+            }
+        }
+    }
+
+    /// Confirms the teleport cursor and sends the planned move.
+    fn confirm_move(&mut self) {
+        let key_cursor = self.model.key_cursor.take();
+        let cursor_valid = key_cursor.is_some_and(|cursor| {
+            self.model.shared.is_walkable(cursor) && !self.model.shared.is_blocked_by_trail(cursor)
+        });
+        self.drag = None;
+        let Some(player) = self.model.shared.players.get_mut(&self.model.player_id) else {
+            return;
+        };
+        if let Some(teleport_to) = key_cursor
+            && cursor_valid
+            && player.is_channeling
+        {
+            player.submitted_move = PlayerMove::TeleportActivate { teleport_to };
+        }
+        self.connection
+            .send(ClientMessage::SubmitMove(player.submitted_move.clone()));
+        let mut sfx = self.assets.sounds.click.play();
+        sfx.set_volume(self.settings.borrow().volume());
+    }
+
     fn mouse_press(&mut self) {
         let Some(cursor_pos) = self.cursor_pos else {
             return;
         };
+        let over_sidebar = self.model.spectating
+            && (self.ui.players.iter().any(|(_, widget)| widget.hovered)
+                || self.ui.show_moves.hovered
+                || self.ui.join.hovered);
+        if over_sidebar
+            || self.ui.minimap.hovered
+            || self.ui.history.hovered
+            || self.ui.history_toggle.hovered
+        {
+            return;
+        }
+
+        if self.model.spectating {
+            // Follow the clicked player or pan the camera
+            let clicked = self
+                .model
+                .shared
+                .players
+                .values()
+                .find(|player| player.pos == cursor_pos.grid);
+            match clicked {
+                Some(player) => self.model.follow = Some(player.id),
+                None => {
+                    self.drag = Some(Drag {
+                        target: DragTarget::Camera {
+                            last: cursor_pos.screen,
+                        },
+                    })
+                }
+            }
+            return;
+        }
 
         let player_drag = self
             .model
@@ -193,11 +449,30 @@ impl Game {
                         .send(ClientMessage::SubmitMove(player.submitted_move.clone()));
                 }
             }
-        } else if let Some(player) = self.model.shared.players.get_mut(&self.model.player_id) {
+        } else {
+            let cursor_blocked = !self.model.shared.is_walkable(cursor_pos.grid)
+                || self.model.shared.is_blocked_by_trail(cursor_pos.grid);
+            let click_path = self
+                .model
+                .shared
+                .players
+                .get(&self.model.player_id)
+                .and_then(|player| {
+                    let sprint = matches!(
+                        player.submitted_move,
+                        PlayerMove::Normal { sprint: true, .. }
+                    );
+                    self.model
+                        .shared
+                        .find_path(player.pos, cursor_pos.grid, player.speed(sprint))
+                });
+            let is_planning = matches!(self.model.shared.phase, Phase::Planning { .. });
+            let Some(player) = self.model.shared.players.get_mut(&self.model.player_id) else {
+                return;
+            };
             if player.is_channeling {
-                if !self.model.shared.map.walls.contains(&cursor_pos.grid)
-                    && self.model.shared.map.is_in_bounds(cursor_pos.grid)
-                    && shared::distance(player.pos, cursor_pos.grid) <= 3
+                if !cursor_blocked
+                    && shared::distance(player.pos, cursor_pos.grid) <= player.stats.teleport_range
                 {
                     player.submitted_move = PlayerMove::TeleportActivate {
                         teleport_to: cursor_pos.grid,
@@ -205,17 +480,83 @@ impl Game {
                     self.connection
                         .send(ClientMessage::SubmitMove(player.submitted_move.clone()));
                 }
-            } else if let PlayerMove::Throw { direction } = &mut player.submitted_move {
+            } else if let PlayerMove::Throw { direction } | PlayerMove::BuildWall { direction } =
+                &mut player.submitted_move
+            {
                 let new_dir = (cursor_pos.grid - player.pos).map(|x| x.clamp_abs(1));
                 if new_dir.x.abs() + new_dir.y.abs() == 1 {
                     *direction = new_dir;
                     self.connection
                         .send(ClientMessage::SubmitMove(player.submitted_move.clone()));
                 }
+            } else if let Some(new_path) = click_path
+                && is_planning
+                && player.stunned_duration.is_none()
+            {
+                // Click to move along the shortest path
+                match &mut player.submitted_move {
+                    PlayerMove::Normal { path, .. } => *path = new_path,
+                    _ => {
+                        player.submitted_move = PlayerMove::Normal {
+                            path: new_path,
+                            sprint: false,
+                        };
+                    }
+                }
+                self.connection
+                    .send(ClientMessage::SubmitMove(player.submitted_move.clone()));
             }
         }
     }
 
+    /// Starts dragging the camera with the cursor.
+    fn start_pan(&mut self) {
+        if let Some(cursor_pos) = self.cursor_pos
+            && self.drag.is_none()
+        {
+            self.drag = Some(Drag {
+                target: DragTarget::Camera {
+                    last: cursor_pos.screen,
+                },
+            });
+        }
+    }
+
+    /// A second finger turns the touch into a pinch controlling the camera.
+    fn start_pinch(&mut self, touch_id: u64, position: vec2<f64>) {
+        let (Some(first), Some(cursor_pos)) = (self.active_touch, self.cursor_pos) else {
+            return;
+        };
+        self.drag = None;
+        self.pinch = Some(Pinch {
+            touches: [(first, cursor_pos.screen), (touch_id, position)],
+        });
+    }
+
+    fn pinch_move(&mut self, touch_id: u64, position: vec2<f64>) {
+        let Some(pinch) = &mut self.pinch else {
+            return;
+        };
+        let Some(i) = pinch.touches.iter().position(|&(id, _)| id == touch_id) else {
+            return;
+        };
+        let before = pinch.touches;
+        pinch.touches[i].1 = position;
+        let after = pinch.touches;
+
+        let midpoint = |touches: [(u64, vec2<f64>); 2]| (touches[0].1 + touches[1].1) / 2.0;
+        let spread = |touches: [(u64, vec2<f64>); 2]| (touches[0].1 - touches[1].1).len();
+        let framebuffer_size = self.framebuffer_size.as_f32();
+        let camera = &self.model.camera;
+        let delta = camera.screen_to_world(framebuffer_size, midpoint(before).as_f32())
+            - camera.screen_to_world(framebuffer_size, midpoint(after).as_f32());
+        self.model.pan_camera(delta);
+        if spread(before) > 0.0 {
+            self.model
+                .zoom_camera((spread(after) / spread(before)) as f32);
+        }
+    }
+
     fn mouse_release(&mut self) {
         if let Some(drag) = self.drag.take() {
             match drag.target {
@@ -228,6 +569,7 @@ impl Game {
                     self.connection
                         .send(ClientMessage::SubmitMove(player.submitted_move.clone()));
                 }
+                DragTarget::Camera { .. } => {}
             }
         }
     }
@@ -248,58 +590,81 @@ impl Game {
         if let Some(drag) = &mut self.drag {
             match &mut drag.target {
                 DragTarget::Player { path } => {
-                    let Some(player) = self.model.shared.players.get_mut(&self.model.player_id)
-                    else {
-                        return;
-                    };
-
-                    let sprint = matches!(
-                        player.submitted_move,
-                        PlayerMove::Normal { sprint: true, .. }
-                    );
-
-                    let mut update = false;
-                    if path
-                        .len()
-                        .checked_sub(2)
-                        .and_then(|i| path.get(i))
-                        .is_some_and(|&prev_pos| prev_pos == cursor_pos.grid)
-                    {
-                        // Cancel last move
-                        path.pop();
-                        update = true;
-                    } else if path.len() <= player.speed(sprint)
-                        && !path.contains(&cursor_pos.grid)
-                        && let Some(&last) = path.last()
-                        && shared::are_adjacent(last, cursor_pos.grid)
-                        && !self.model.shared.map.walls.contains(&cursor_pos.grid)
-                        && self.model.shared.map.is_in_bounds(cursor_pos.grid)
-                    {
-                        // Add tile
-                        path.push(cursor_pos.grid);
-                        update = true;
-                    }
-
+                    let update =
+                        self.model
+                            .shared
+                            .step_path(self.model.player_id, path, cursor_pos.grid);
                     if update {
-                        match &mut player.submitted_move {
-                            PlayerMove::Normal {
-                                path: move_path, ..
-                            } => *move_path = path.clone(),
-                            _ => {
-                                player.submitted_move = PlayerMove::Normal {
-                                    path: path.clone(),
-                                    sprint: false,
-                                };
-                            }
-                        }
-                        self.connection
-                            .send(ClientMessage::SubmitMove(player.submitted_move.clone()));
+                        let path = path.clone();
+                        self.submit_path(path);
                     }
                 }
+                DragTarget::Camera { last } => {
+                    let framebuffer_size = self.framebuffer_size.as_f32();
+                    let camera = &self.model.camera;
+                    let delta = camera.screen_to_world(framebuffer_size, last.as_f32())
+                        - camera.screen_to_world(framebuffer_size, position.as_f32());
+                    *last = position;
+                    self.model.pan_camera(delta);
+                }
             }
         }
     }
 
+    /// Asks the server to let the spectator play with the last used customization.
+    /// Shows the error with the option to reconnect, as a player again if playing.
+    fn connection_lost(&mut self, error: String) {
+        let customization = self
+            .model
+            .shared
+            .players
+            .get(&self.model.player_id)
+            .filter(|_| !self.model.spectating)
+            .map(|player| player.customization.clone());
+        let join = Join {
+            address: self.address.clone(),
+            customization,
+        };
+        let state = ConnectionError::lost(&self.geng, &self.assets, &self.settings, join, error);
+        self.transition = Some(geng::state::Transition::Switch(Box::new(state)));
+    }
+
+    fn join(&mut self) {
+        let customization = Profile::load()
+            .customization
+            .unwrap_or_else(PlayerCustomization::random);
+        self.ui = GameUi::new(&self.geng, &self.assets, &customization.loadout);
+        self.model.joining = true;
+        self.connection.send(ClientMessage::Join(customization));
+    }
+
+    /// Replaces the planned path, keeping the sprint toggle, and sends it to the server.
+    fn submit_path(&mut self, new_path: Vec<vec2<ICoord>>) {
+        let Some(player) = self.model.shared.players.get_mut(&self.model.player_id) else {
+            return;
+        };
+        match &mut player.submitted_move {
+            PlayerMove::Normal { path, .. } => *path = new_path,
+            _ => {
+                player.submitted_move = PlayerMove::Normal {
+                    path: new_path,
+                    sprint: false,
+                };
+            }
+        }
+        self.connection
+            .send(ClientMessage::SubmitMove(player.submitted_move.clone()));
+    }
+
+    fn use_ability(&mut self, ability: Ability) {
+        match ability {
+            Ability::Sprint => self.ability_sprint(),
+            Ability::Teleport => self.ability_teleport(),
+            Ability::Throw => self.ability_throw(),
+            Ability::Wall => self.ability_wall(),
+        }
+    }
+
     fn ability_sprint(&mut self) {
         let Some(player) = self.model.shared.players.get_mut(&self.model.player_id) else {
             return;
@@ -357,9 +722,30 @@ impl Game {
         self.connection
             .send(ClientMessage::SubmitMove(player.submitted_move.clone()));
     }
+
+    fn ability_wall(&mut self) {
+        let Some(player) = self.model.shared.players.get_mut(&self.model.player_id) else {
+            return;
+        };
+        if player.stunned_duration.is_some() || player.cooldown_wall > 0 || player.is_channeling {
+            return;
+        }
+        player.submitted_move = match player.submitted_move {
+            PlayerMove::BuildWall { .. } => PlayerMove::default(),
+            _ => PlayerMove::BuildWall {
+                direction: vec2(1, 0),
+            },
+        };
+        self.connection
+            .send(ClientMessage::SubmitMove(player.submitted_move.clone()));
+    }
 }
 
 impl geng::State for Game {
+    fn transition(&mut self) -> Option<geng::state::Transition> {
+        self.transition.take()
+    }
+
     fn handle_event(&mut self, event: geng::Event) {
         self.handle_event(event);
     }
@@ -371,23 +757,94 @@ impl geng::State for Game {
                 .cursor
                 .cursor_move(cursor_pos.screen.as_f32());
         }
+        self.ui_context.apply_settings(&self.settings.borrow());
         self.ui_context
             .update(delta_time as f32, self.active_touch.is_some());
+        if self.model.spectating {
+            self.ui
+                .update_players(self.model.shared.players.keys().copied().sorted().collect());
+        }
         self.ui.update(&mut self.ui_context, self.framebuffer_size);
 
-        if self.ui.ability_sprint.mouse_left.just_pressed {
-            self.ability_sprint();
+        if self.model.spectating {
+            for (player_id, widget) in &self.ui.players {
+                if widget.mouse_left.clicked {
+                    self.model.follow = Some(*player_id);
+                }
+            }
+            if self.ui.show_moves.mouse_left.clicked {
+                self.model.show_moves = !self.model.show_moves;
+            }
+            if self.ui.join.mouse_left.clicked && !self.model.joining {
+                self.join();
+            }
+        }
+
+        if self.ui.minimap.mouse_left.pressed.is_some() {
+            // Move the camera to the pointed place
+            let map = &self.model.shared.map;
+            let bounds = crate::render::minimap_bounds(self.ui.minimap.position, map);
+            let world = map.world_bounds().as_f32();
+            let t = (self.ui_context.cursor.position - bounds.min) / bounds.size();
+            self.model.move_camera(world.min + t * world.size());
+        }
+
+        if self.ui.history_toggle.mouse_left.clicked {
+            self.ui.history_open = !self.ui.history_open;
+        }
+
+        let scroll = self.ui_context.cursor.scroll_dir();
+        if scroll != 0 {
+            if self.ui.history.hovered {
+                let lines = history::lines(&self.model.history).len();
+                let max_scroll = lines.saturating_sub(HISTORY_ROWS) as i64;
+                self.ui.history_scroll =
+                    (self.ui.history_scroll as i64 - scroll).clamp(0, max_scroll) as usize;
+            } else {
+                self.model.zoom_camera(1.1_f32.powi(scroll as i32));
+            }
         }
-        if self.ui.ability_teleport.mouse_left.clicked {
-            self.ability_teleport();
+
+        let used: Vec<Ability> = self
+            .ui
+            .abilities
+            .iter()
+            .filter(|(ability, widget)| match ability {
+                Ability::Sprint => widget.mouse_left.just_pressed,
+                _ => widget.mouse_left.clicked,
+            })
+            .map(|&(ability, _)| ability)
+            .collect();
+        for ability in used {
+            self.use_ability(ability);
         }
-        if self.ui.ability_throw.mouse_left.clicked {
-            self.ability_throw();
+
+        // Gamepad input
+        let actions: Vec<Action> = {
+            let settings = self.settings.borrow();
+            self.gamepads
+                .pressed()
+                .into_iter()
+                .filter_map(|button| settings.controls.gamepad_action(button))
+                .collect()
+        };
+        for action in actions {
+            self.handle_action(action);
         }
 
         // Process server messages
-        for message in self.connection.new_messages() {
-            let message = message.unwrap();
+        while let Some(message) = self.connection.next().now_or_never() {
+            let message = match message {
+                Some(Ok(message)) => message,
+                Some(Err(err)) => {
+                    self.connection_lost(format!("{err}"));
+                    return;
+                }
+                None => {
+                    self.connection_lost("the server closed the connection".to_owned());
+                    return;
+                }
+            };
             if let ServerMessage::StartResolution(_) = message
                 && let Some(drag) = &self.drag
                 && let DragTarget::Player { .. } = &drag.target
@@ -408,17 +865,21 @@ impl geng::State for Game {
             let sounds = &self.assets.sounds;
             let sfx = match event {
                 GameEvent::ResultsOver => Some(&sounds.gameover),
-                GameEvent::MushroomPickup(_) => Some(&sounds.gather),
+                GameEvent::MushroomPickup(..) => Some(&sounds.gather),
                 GameEvent::PlayerStunned(..) => Some(&sounds.stunned),
                 GameEvent::Score(..) => Some(&sounds.score),
-                GameEvent::Teleport => Some(&sounds.teleport),
-                GameEvent::MushroomThrow => Some(&sounds.throw_mushroom),
+                GameEvent::Teleport(..) => Some(&sounds.teleport),
+                GameEvent::MushroomThrow(..) => Some(&sounds.throw_mushroom),
+                GameEvent::WallBuilt(_) => Some(&sounds.wall_built),
+                GameEvent::HazardSpawned(_) => Some(&sounds.boulder_roll),
+                GameEvent::BoulderCrumbled(_) => Some(&sounds.boulder_crumble),
+                GameEvent::MushroomRain(_) => Some(&sounds.gather),
                 GameEvent::NextMove => Some(&sounds.walk),
                 _ => None,
             };
             if let Some(sfx) = sfx {
                 let mut sfx = sfx.play();
-                sfx.set_volume(0.5);
+                sfx.set_volume(self.settings.borrow().volume());
             }
         }
     }
@@ -440,50 +901,77 @@ impl geng::State for Game {
         );
 
         self.render.draw_game(&mut self.model, framebuffer);
-        self.render.draw_game_ui(&self.model, &self.ui, framebuffer);
+        let settings = self.settings.borrow();
+        self.render
+            .draw_game_ui(&self.model, &self.ui, &settings.controls, framebuffer);
+        self.render
+            .draw_minimap(&self.model, &self.ui.minimap, framebuffer);
         self.ui_context.frame_end();
 
-        ugli::draw(
-            final_buffer,
-            &self.assets.shaders.crt,
-            ugli::DrawMode::TriangleFan,
+        crate::render::draw_crt(
+            &self.geng,
+            &self.assets,
             &self.unit_quad,
-            ugli::uniforms! {
-                u_texture: &self.post_texture,
-                u_curvature: 50.0,
-                u_vignette_multiplier: 0.1,
-                u_scanlines_multiplier: 0.1,
-                u_time: self.time.as_f32(),
-            },
-            ugli::DrawParameters::default(),
+            &self.post_texture,
+            settings.crt_strength,
+            self.time.as_f32(),
+            final_buffer,
         );
     }
 }
 
 impl GameUi {
-    pub fn new(_geng: &Geng, _assets: &Rc<Assets>) -> Self {
+    pub fn new(_geng: &Geng, _assets: &Rc<Assets>, loadout: &Loadout) -> Self {
         Self {
-            ability_sprint: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
-            ability_teleport: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
-            ability_throw: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+            abilities: loadout
+                .abilities
+                .iter()
+                .map(|&ability| {
+                    (
+                        ability,
+                        WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+                    )
+                })
+                .collect(),
             mushrooms: WidgetState::new(),
+            players: Vec::new(),
+            show_moves: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+            join: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+            minimap: WidgetState::new(),
+            history_toggle: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+            history_open: false,
+            history: WidgetState::new(),
+            history_rows: (0..HISTORY_ROWS).map(|_| WidgetState::new()).collect(),
+            history_scroll: 0,
         }
     }
 
+    /// Keeps a sidebar row for each of the players.
+    pub fn update_players(&mut self, player_ids: Vec<ClientId>) {
+        let mut rows = std::mem::take(&mut self.players);
+        self.players = player_ids
+            .into_iter()
+            .map(|player_id| {
+                let widget = rows
+                    .iter()
+                    .position(|(id, _)| *id == player_id)
+                    .map(|i| rows.swap_remove(i).1)
+                    .unwrap_or_else(|| WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()));
+                (player_id, widget)
+            })
+            .collect();
+    }
+
     pub fn update(&mut self, context: &mut UiContext, framebuffer_size: vec2<usize>) {
         let screen = Aabb2::ZERO.extend_positive(framebuffer_size.as_f32());
         context.screen = screen;
 
-        let layout_size = screen.height() * 0.05;
+        let layout_size = screen.height() * 0.05 * context.scale;
 
         let ability_size = vec2::splat(2.5 * layout_size);
         let mut pos = screen.bottom_left() + vec2::splat(1.0 * layout_size);
 
-        for ability in [
-            &mut self.ability_sprint,
-            &mut self.ability_teleport,
-            &mut self.ability_throw,
-        ] {
+        for (_, ability) in &mut self.abilities {
             ability.update(Aabb2::point(pos).extend_positive(ability_size), context);
             pos.x += ability_size.x;
         }
@@ -506,5 +994,70 @@ impl GameUi {
                 .extend_symmetric(vec2(0.0, mushrooms_size.y / 2.0)),
             context,
         );
+
+        // Spectator sidebar
+        let row_size = vec2(8.0, 1.5) * layout_size;
+        let mut pos = screen.top_left() + vec2(0.5, -0.5) * layout_size;
+        for (_, widget) in &mut self.players {
+            widget.update(
+                Aabb2::point(pos)
+                    .extend_right(row_size.x)
+                    .extend_down(row_size.y),
+                context,
+            );
+            pos.y -= row_size.y;
+        }
+        self.show_moves.update(
+            Aabb2::point(pos - vec2(0.0, 0.5 * layout_size))
+                .extend_right(row_size.x)
+                .extend_down(layout_size),
+            context,
+        );
+        self.join.update(
+            Aabb2::point(pos - vec2(0.0, 2.0 * layout_size))
+                .extend_right(row_size.x)
+                .extend_down(layout_size),
+            context,
+        );
+
+        // Minimap next to the score panel
+        let score_panel_width =
+            context.assets.sprites.score_panel.size().as_f32().aspect() * screen.height();
+        let minimap_size = vec2::splat(6.0 * layout_size);
+        self.minimap.update(
+            Aabb2::point(
+                screen.bottom_right()
+                    + vec2(-score_panel_width - 0.5 * layout_size, 0.5 * layout_size),
+            )
+            .extend_left(minimap_size.x)
+            .extend_up(minimap_size.y),
+            context,
+        );
+
+        // Turn history above the abilities
+        let history_row = vec2(10.0, 0.7) * layout_size;
+        let toggle_pos = screen.bottom_left() + vec2(1.0, 4.0) * layout_size;
+        self.history_toggle.update(
+            Aabb2::point(toggle_pos)
+                .extend_right(history_row.x * 0.4)
+                .extend_up(layout_size),
+            context,
+        );
+        let panel = Aabb2::point(toggle_pos + vec2(0.0, 1.5 * layout_size))
+            .extend_right(history_row.x)
+            .extend_up(history_row.y * HISTORY_ROWS as f32);
+        self.history.visible = self.history_open;
+        self.history.update(panel, context);
+        let mut pos = panel.top_left();
+        for row in &mut self.history_rows {
+            row.visible = self.history_open;
+            row.update(
+                Aabb2::point(pos)
+                    .extend_right(history_row.x)
+                    .extend_down(history_row.y),
+                context,
+            );
+            pos.y -= history_row.y;
+        }
     }
 }
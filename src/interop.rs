@@ -13,14 +13,22 @@ pub enum ServerMessage {
     Sync(shared::SharedModel),
     StartResolution(shared::SharedModel),
     FinishResolution(shared::SharedModel),
+    /// The model after a resolution move and the events of that move.
+    ResolveMove(shared::SharedModel, Vec<shared::GameEvent>),
     PlayerCustomization(ClientId, PlayerCustomization),
+    Status(ServerStatus),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ClientMessage {
     Pong,
+    /// Asks for the server status without joining the game.
+    QueryStatus,
     SetCustomization(PlayerCustomization),
     Spectate,
+    /// Stops spectating and joins the current match if it has not started yet,
+    /// or the next one.
+    Join(PlayerCustomization),
     SubmitMove(PlayerMove),
 }
 
@@ -29,3 +37,29 @@ pub struct Setup {
     pub player_id: ClientId,
     pub model: shared::SharedModel,
 }
+
+/// Public information about the server for server lists and monitoring.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerStatus {
+    pub version: String,
+    /// Seconds since the server has started.
+    pub uptime: f32,
+    pub rooms: Vec<RoomStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomStatus {
+    pub name: String,
+    pub players: usize,
+    pub spectators: usize,
+    pub phase: shared::Phase,
+    pub turn_current: Turns,
+    pub turns_max: Turns,
+}
+
+impl ServerStatus {
+    /// Players in all rooms, not counting the spectators.
+    pub fn players(&self) -> usize {
+        self.rooms.iter().map(|room| room.players).sum()
+    }
+}
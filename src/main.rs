@@ -2,11 +2,14 @@
 mod server;
 
 mod assets;
+mod connect;
+mod controls;
 mod game;
 mod interop;
 mod menu;
 mod model;
 mod render;
+mod settings;
 mod ui;
 
 use geng::prelude::*;
@@ -17,6 +20,11 @@ struct Args {
     pub server: Option<String>,
     #[clap(long)]
     pub connect: Option<String>,
+    /// Map file for the server, uses the default map if not set.
+    #[clap(long)]
+    pub map: Option<std::path::PathBuf>,
+    #[clap(flatten)]
+    pub rules: model::Rules,
     #[clap(flatten)]
     pub geng: geng::CliArgs,
 }
@@ -31,6 +39,13 @@ fn main() {
     geng_options.window.title = "Down to Trail".to_string();
     geng_options.with_cli(&args.geng);
 
+    // Remember the server address to offer it in the menu next time
+    if args.connect.is_some() {
+        let mut profile = settings::Profile::load();
+        profile.connect = args.connect.clone();
+        profile.save();
+    }
+
     if args.connect.is_none() && args.server.is_none() {
         #[cfg(target_arch = "wasm32")]
         {
@@ -63,8 +78,10 @@ fn main() {
     if args.server.is_some() && args.connect.is_none() {
         #[cfg(not(target_arch = "wasm32"))]
         {
-            let server =
-                geng::net::Server::new(server::App::new(), args.server.as_deref().unwrap());
+            let server = geng::net::Server::new(
+                server::App::new(args.rules, args.map),
+                args.server.as_deref().unwrap(),
+            );
             let server_handle = server.handle();
             ctrlc::set_handler(move || server_handle.shutdown()).unwrap();
             server.run();
@@ -72,7 +89,10 @@ fn main() {
     } else {
         #[cfg(not(target_arch = "wasm32"))]
         let server = if let Some(addr) = &args.server {
-            let server = geng::net::Server::new(server::App::new(), addr);
+            let server = geng::net::Server::new(
+                server::App::new(args.rules.clone(), args.map.clone()),
+                addr,
+            );
             let server_handle = server.handle();
             let server_thread = std::thread::spawn(move || {
                 server.run();
@@ -85,7 +105,8 @@ fn main() {
         Geng::run_with(&geng_options, move |geng| async move {
             let manager = geng.asset_manager();
             let assets = assets::Assets::load(manager).await.unwrap();
-            let state = menu::MainMenu::new(&geng, &Rc::new(assets), args.connect).await;
+            let settings = Rc::new(std::cell::RefCell::new(settings::Settings::load()));
+            let state = menu::MainMenu::new(&geng, &Rc::new(assets), &settings, args.connect).await;
             geng.run_state(state).await;
         });
 
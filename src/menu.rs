@@ -1,16 +1,23 @@
 use crate::{
     assets::Assets,
-    model::Character,
+    connect::{Join, ServerEntry, ServerEntryStatus},
+    model::{Character, LOADOUT_SIZE, Loadout, PlayerCustomization},
+    settings::{Profile, Settings, SettingsMenu},
     ui::{UiContext, WidgetSfxConfig, WidgetState},
 };
 
-use geng::prelude::*;
+use std::cell::RefCell;
+
+use geng::prelude::{itertools::Itertools, *};
 use geng_utils::conversions::*;
 
+/// Number of servers that fit in the server list.
+const SERVER_LIST_SIZE: usize = 8;
+
 pub struct MainMenu {
     geng: Geng,
     assets: Rc<Assets>,
-    connect: Option<String>,
+    settings: Rc<RefCell<Settings>>,
     transition: Option<geng::state::Transition>,
     unit_quad: ugli::VertexBuffer<draw2d::TexturedVertex>,
     ui_context: UiContext,
@@ -25,11 +32,26 @@ pub struct MainMenu {
     colors: Vec<Rgba<f32>>,
     color_i: usize,
     name: String,
+    loadout: Loadout,
+
+    /// The address to connect to.
+    address: String,
+    /// The default server followed by the favorites.
+    servers: Vec<ServerEntry>,
+    editing: Option<TextField>,
+}
+
+/// The text field being edited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextField {
+    Name,
+    Address,
 }
 
 pub struct MainMenuUi {
     pub join: WidgetState,
     pub spectate: WidgetState,
+    pub settings: WidgetState,
     pub name: WidgetState,
     pub character: WidgetState,
     pub skin_prev: WidgetState,
@@ -38,12 +60,41 @@ pub struct MainMenuUi {
     pub color_prev: WidgetState,
     pub color_text: WidgetState,
     pub color_next: WidgetState,
+    pub stats: WidgetState,
+    pub passive: WidgetState,
+    pub loadout: Vec<WidgetState>,
+    pub address: WidgetState,
+    pub favorite: WidgetState,
+    pub refresh: WidgetState,
+    pub servers: Vec<WidgetState>,
 }
 
 impl MainMenu {
-    pub async fn new(geng: &Geng, assets: &Rc<Assets>, connect: Option<String>) -> Self {
+    pub async fn new(
+        geng: &Geng,
+        assets: &Rc<Assets>,
+        settings: &Rc<RefCell<Settings>>,
+        connect: Option<String>,
+    ) -> Self {
+        let profile = Profile::load();
+        let saved = profile.customization;
+        // The launch address comes first, followed by the remembered one and the favorites
+        let servers: Vec<ServerEntry> = connect
+            .iter()
+            .chain(&profile.connect)
+            .chain(&profile.favorites)
+            .unique()
+            .map(|address| ServerEntry::new(address.clone(), profile.favorites.contains(address)))
+            .collect();
+        let address = profile.connect.or(connect).unwrap_or_default();
+
         let characters: Vec<Character> = Character::all().into();
-        let character = Character::random();
+        let character = saved
+            .as_ref()
+            .map_or_else(Character::random, |saved| saved.character);
+        let color = saved
+            .as_ref()
+            .map_or(character.color(), |saved| saved.color);
         let colors = vec![
             Rgba::try_from("#6D767B").unwrap(),
             Rgba::try_from("#5590B4").unwrap(),
@@ -59,7 +110,7 @@ impl MainMenu {
         Self {
             geng: geng.clone(),
             assets: assets.clone(),
-            connect,
+            settings: settings.clone(),
             transition: None,
             unit_quad: geng_utils::geometry::unit_quad_geometry(geng.ugli()),
             ui_context: UiContext::new(geng, assets),
@@ -74,14 +125,84 @@ impl MainMenu {
                 .position(|char| *char == character)
                 .unwrap_or(0),
             characters,
-            color_i: colors
-                .iter()
-                .position(|color| *color == character.color())
-                .unwrap_or(0),
+            color_i: colors.iter().position(|&c| c == color).unwrap_or(0),
             colors,
-            name: String::new(),
+            name: saved
+                .as_ref()
+                .map(|saved| saved.name.clone())
+                .unwrap_or_default(),
+            loadout: saved.map(|saved| saved.loadout).unwrap_or_default(),
+
+            address,
+            servers,
+            editing: None,
         }
     }
+
+    fn start_text_edit(&mut self, field: TextField) {
+        self.editing = Some(field);
+        let text = match field {
+            TextField::Name => &self.name,
+            TextField::Address => &self.address,
+        };
+        self.geng.window().start_text_edit(text);
+    }
+
+    /// Adds or removes the current address from the favorite servers.
+    fn toggle_favorite(&mut self) {
+        if self.address.is_empty() {
+            return;
+        }
+        let mut profile = Profile::load();
+        if let Some(i) = profile
+            .favorites
+            .iter()
+            .position(|favorite| *favorite == self.address)
+        {
+            profile.favorites.remove(i);
+            if let Some(i) = self
+                .servers
+                .iter()
+                .position(|server| server.address == self.address)
+            {
+                // Keep the default server in the list
+                if i == 0 {
+                    self.servers[i].favorite = false;
+                } else {
+                    self.servers.remove(i);
+                }
+            }
+        } else {
+            profile.favorites.push(self.address.clone());
+            match self
+                .servers
+                .iter_mut()
+                .find(|server| server.address == self.address)
+            {
+                Some(server) => server.favorite = true,
+                None => self
+                    .servers
+                    .push(ServerEntry::new(self.address.clone(), true)),
+            }
+        }
+        profile.save();
+    }
+
+    fn join(&mut self, customization: Option<PlayerCustomization>) {
+        if self.address.is_empty() {
+            return;
+        }
+        let state = crate::connect::connect(
+            &self.geng,
+            &self.assets,
+            &self.settings,
+            Join {
+                address: self.address.clone(),
+                customization,
+            },
+        );
+        self.transition = Some(geng::state::Transition::Push(Box::new(state)));
+    }
 }
 
 impl geng::State for MainMenu {
@@ -89,18 +210,43 @@ impl geng::State for MainMenu {
         let transition = self.transition.take();
         if transition.is_some() {
             self.geng.window().stop_text_edit();
+            self.editing = None;
         }
         transition
     }
 
     fn update(&mut self, delta_time: f64) {
         self.time += delta_time as f32;
+        self.ui_context.apply_settings(&self.settings.borrow());
         self.ui_context
             .update(delta_time as f32, self.active_touch.is_some());
         self.ui.update(&mut self.ui_context, self.framebuffer_size);
 
         if self.ui.name.mouse_left.clicked {
-            self.geng.window().start_text_edit(&self.name);
+            self.start_text_edit(TextField::Name);
+        }
+        if self.ui.address.mouse_left.clicked {
+            self.start_text_edit(TextField::Address);
+        }
+
+        for server in &mut self.servers {
+            server.update();
+        }
+        if self.ui.refresh.mouse_left.clicked {
+            for server in &mut self.servers {
+                server.refresh();
+            }
+        }
+        if self.ui.favorite.mouse_left.clicked {
+            self.toggle_favorite();
+        }
+        for (server, widget) in self.servers.iter().zip(&self.ui.servers) {
+            if widget.mouse_left.clicked {
+                self.address = server.address.clone();
+                if self.editing == Some(TextField::Address) {
+                    self.geng.window().start_text_edit(&self.address);
+                }
+            }
         }
 
         if self.ui.color_prev.mouse_left.clicked {
@@ -145,47 +291,29 @@ impl geng::State for MainMenu {
                 .unwrap_or(0);
         }
 
+        for (slot, widget) in self.ui.loadout.iter().enumerate() {
+            if widget.mouse_left.clicked {
+                self.loadout.cycle_slot(slot);
+            }
+        }
+
         if self.ui.join.mouse_left.clicked {
-            let future = {
-                let geng = self.geng.clone();
-                let assets = self.assets.clone();
-                let connect = self.connect.clone();
-                let customization = crate::model::PlayerCustomization {
-                    name: self.name.clone(),
-                    character: self.characters[self.character_i],
-                    color: self.colors[self.color_i],
-                };
-                async move {
-                    let connection = geng::net::client::connect(&connect.unwrap()).await.unwrap();
-                    crate::game::Game::new(&geng, &assets, connection, customization).await
-                }
+            let customization = PlayerCustomization {
+                name: self.name.clone(),
+                character: self.characters[self.character_i],
+                color: self.colors[self.color_i],
+                loadout: self.loadout,
             };
-            let state = {
-                geng::LoadingScreen::new(
-                    &self.geng,
-                    geng::EmptyLoadingScreen::new(&self.geng),
-                    future,
-                )
-            };
-            self.transition = Some(geng::state::Transition::Push(Box::new(state)));
+            let mut profile = Profile::load();
+            profile.customization = Some(customization.clone());
+            profile.save();
+            self.join(Some(customization));
         }
         if self.ui.spectate.mouse_left.clicked {
-            let future = {
-                let geng = self.geng.clone();
-                let assets = self.assets.clone();
-                let connect = self.connect.clone();
-                async move {
-                    let connection = geng::net::client::connect(&connect.unwrap()).await.unwrap();
-                    crate::game::Game::new_spectator(&geng, &assets, connection).await
-                }
-            };
-            let state = {
-                geng::LoadingScreen::new(
-                    &self.geng,
-                    geng::EmptyLoadingScreen::new(&self.geng),
-                    future,
-                )
-            };
+            self.join(None);
+        }
+        if self.ui.settings.mouse_left.clicked {
+            let state = SettingsMenu::new(&self.geng, &self.assets, &self.settings);
             self.transition = Some(geng::state::Transition::Push(Box::new(state)));
         }
     }
@@ -215,7 +343,11 @@ impl geng::State for MainMenu {
             //         self.name.push(char);
             //     }
             // }
-            geng::Event::EditText(name) => self.name = name,
+            geng::Event::EditText(text) => match self.editing {
+                Some(TextField::Name) => self.name = text,
+                Some(TextField::Address) => self.address = text,
+                None => (),
+            },
             _ => (),
         }
     }
@@ -343,6 +475,51 @@ impl geng::State for MainMenu {
             .fit_into(self.ui.color_text.position),
         );
 
+        // Stats only apply with the asymmetric rules, which the server decides on
+        let character = self.characters[self.character_i];
+        let stats = character.stats();
+        let capacity = stats
+            .mushroom_capacity
+            .map_or_else(|| "∞".to_owned(), |capacity| capacity.to_string());
+        self.geng.draw2d().draw2d(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            &draw2d::Text::unit(
+                self.assets.font.clone(),
+                format!(
+                    "spd {} bag {capacity} tp {} res {}",
+                    stats.max_speed, stats.teleport_range, stats.stun_resistance
+                ),
+                Rgba::try_from("#42343B").unwrap(),
+            )
+            .fit_into(self.ui.stats.position),
+        );
+        self.geng.draw2d().draw2d(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            &draw2d::Text::unit(
+                self.assets.font.clone(),
+                format!("{} (asymmetric rules)", character.passive().name()),
+                Rgba::try_from("#474C80").unwrap(),
+            )
+            .fit_into(self.ui.passive.position),
+        );
+
+        for (widget, &ability) in self.ui.loadout.iter().zip(&self.loadout.abilities) {
+            let feedback = if widget.hovered {
+                widget.position.width() * 0.1
+            } else {
+                0.0
+            };
+            self.geng.draw2d().textured_quad(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                widget.position.extend_uniform(feedback),
+                crate::render::get_ability_sprite(&sprites.abilities, ability, true),
+                Rgba::WHITE,
+            );
+        }
+
         let texture = button_variant(
             &self.ui.join,
             &sprites.join,
@@ -363,21 +540,121 @@ impl geng::State for MainMenu {
             .fit(self.ui.spectate.position, vec2(0.5, 0.5))
             .draw(&geng::PixelPerfectCamera, &self.geng, framebuffer);
 
+        self.geng.draw2d().draw2d(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            &draw2d::Text::unit(
+                self.assets.font.clone(),
+                "Settings",
+                if self.ui.settings.hovered {
+                    Rgba::try_from("#5590B4").unwrap()
+                } else {
+                    Rgba::try_from("#B4A091").unwrap()
+                },
+            )
+            .fit_into(self.ui.settings.position),
+        );
+
+        // Server list
+        self.geng.draw2d().quad(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            self.ui.address.position,
+            Rgba::try_from("#B4A091").unwrap(),
+        );
+        self.geng.draw2d().draw2d(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            &draw2d::Text::unit(
+                self.assets.font.clone(),
+                if self.address.is_empty() {
+                    "address.."
+                } else {
+                    &self.address
+                },
+                Rgba::try_from("#42343B").unwrap(),
+            )
+            .fit_into(
+                self.ui
+                    .address
+                    .position
+                    .extend_uniform(-0.2 * self.ui.address.position.height()),
+            ),
+        );
+        let text_color = |state: &WidgetState| {
+            if state.hovered {
+                Rgba::try_from("#5590B4").unwrap()
+            } else {
+                Rgba::try_from("#B4A091").unwrap()
+            }
+        };
+        let is_favorite = self
+            .servers
+            .iter()
+            .any(|server| server.favorite && server.address == self.address);
+        for (text, widget) in [
+            (
+                if is_favorite { "Unsave" } else { "Save" },
+                &self.ui.favorite,
+            ),
+            ("Refresh", &self.ui.refresh),
+        ] {
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &draw2d::Text::unit(self.assets.font.clone(), text, text_color(widget))
+                    .fit_into(widget.position),
+            );
+        }
+        for (server, widget) in self.servers.iter().zip(&self.ui.servers) {
+            let color = if server.address == self.address {
+                Rgba::try_from("#E5BD85").unwrap()
+            } else {
+                text_color(widget)
+            };
+            let status = match &server.status {
+                ServerEntryStatus::Querying(_) => "..".to_owned(),
+                ServerEntryStatus::Online(info) => {
+                    format!("{}p {:.0}ms", info.status.players(), info.ping * 1000.0)
+                }
+                ServerEntryStatus::Offline => "offline".to_owned(),
+            };
+            let position = widget.position;
+            let split = position.min.x + position.width() * 0.65;
+            let address = Aabb2::from_corners(position.min, vec2(split, position.max.y));
+            let status_pos = Aabb2::from_corners(vec2(split, position.min.y), position.max);
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &draw2d::Text::unit(
+                    self.assets.font.clone(),
+                    if server.favorite {
+                        format!("* {}", server.address)
+                    } else {
+                        server.address.clone()
+                    },
+                    color,
+                )
+                .fit_into(address.extend_uniform(-position.height() * 0.1)),
+            );
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &draw2d::Text::unit(self.assets.font.clone(), status, color)
+                    .fit_into(status_pos.extend_uniform(-position.height() * 0.1)),
+            );
+        }
+
         self.ui_context.frame_end();
 
-        ugli::draw(
-            final_buffer,
-            &self.assets.shaders.crt,
-            ugli::DrawMode::TriangleFan,
+        crate::render::draw_crt(
+            &self.geng,
+            &self.assets,
             &self.unit_quad,
-            ugli::uniforms! {
-                u_texture: &self.post_texture,
-                u_curvature: 50.0,
-                u_vignette_multiplier: 0.1,
-                u_scanlines_multiplier: 0.1,
-                u_time: self.time,
-            },
-            ugli::DrawParameters::default(),
+            &self.post_texture,
+            self.settings.borrow().crt_strength,
+            self.time,
+            final_buffer,
         );
     }
 }
@@ -387,6 +664,7 @@ impl MainMenuUi {
         Self {
             join: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
             spectate: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+            settings: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
             name: WidgetState::new(),
             character: WidgetState::new(),
             skin_prev: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
@@ -395,6 +673,17 @@ impl MainMenuUi {
             color_prev: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
             color_text: WidgetState::new(),
             color_next: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+            stats: WidgetState::new(),
+            passive: WidgetState::new(),
+            loadout: (0..LOADOUT_SIZE)
+                .map(|_| WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()))
+                .collect(),
+            address: WidgetState::new(),
+            favorite: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+            refresh: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+            servers: (0..SERVER_LIST_SIZE)
+                .map(|_| WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()))
+                .collect(),
         }
     }
 
@@ -434,6 +723,39 @@ impl MainMenuUi {
         );
         self.spectate.update(spectate, context);
 
+        let settings = Aabb2::from_corners(
+            screen.top_right() + vec2(-60.0, -4.0) / vec2(320.0, 180.0) * screen.size(),
+            screen.top_right() + vec2(-4.0, -14.0) / vec2(320.0, 180.0) * screen.size(),
+        );
+        self.settings.update(settings, context);
+
+        let address = Aabb2::from_corners(
+            screen.top_left() + vec2(232.0, -22.0) / vec2(320.0, 180.0) * screen.size(),
+            screen.top_left() + vec2(316.0, -32.0) / vec2(320.0, 180.0) * screen.size(),
+        );
+        self.address.update(address, context);
+
+        let favorite = Aabb2::from_corners(
+            screen.top_left() + vec2(232.0, -35.0) / vec2(320.0, 180.0) * screen.size(),
+            screen.top_left() + vec2(272.0, -41.0) / vec2(320.0, 180.0) * screen.size(),
+        );
+        self.favorite.update(favorite, context);
+
+        let refresh = Aabb2::from_corners(
+            screen.top_left() + vec2(276.0, -35.0) / vec2(320.0, 180.0) * screen.size(),
+            screen.top_left() + vec2(316.0, -41.0) / vec2(320.0, 180.0) * screen.size(),
+        );
+        self.refresh.update(refresh, context);
+
+        for (i, widget) in self.servers.iter_mut().enumerate() {
+            let top = 45.0 + 10.0 * i as f32;
+            let server = Aabb2::from_corners(
+                screen.top_left() + vec2(232.0, -top) / vec2(320.0, 180.0) * screen.size(),
+                screen.top_left() + vec2(316.0, -top - 8.0) / vec2(320.0, 180.0) * screen.size(),
+            );
+            widget.update(server, context);
+        }
+
         let skin_prev = Aabb2::from_corners(
             main.top_left() + vec2(56.0, -10.0) / vec2(124.0, 62.0) * main.size(),
             main.top_left() + vec2(59.0, -14.0) / vec2(124.0, 62.0) * main.size(),
@@ -469,6 +791,27 @@ impl MainMenuUi {
             main.top_left() + vec2(106.0, -25.0) / vec2(124.0, 62.0) * main.size(),
         );
         self.color_next.update(color_next, context);
+
+        let stats = Aabb2::from_corners(
+            main.top_left() + vec2(60.0, -27.0) / vec2(124.0, 62.0) * main.size(),
+            main.top_left() + vec2(102.0, -30.0) / vec2(124.0, 62.0) * main.size(),
+        );
+        self.stats.update(stats, context);
+
+        let passive = Aabb2::from_corners(
+            main.top_left() + vec2(60.0, -31.0) / vec2(124.0, 62.0) * main.size(),
+            main.top_left() + vec2(102.0, -34.0) / vec2(124.0, 62.0) * main.size(),
+        );
+        self.passive.update(passive, context);
+
+        for (slot, widget) in self.loadout.iter_mut().enumerate() {
+            let left = 13.0 + 12.0 * slot as f32;
+            let slot = Aabb2::from_corners(
+                main.top_left() + vec2(left, -50.0) / vec2(124.0, 62.0) * main.size(),
+                main.top_left() + vec2(left + 11.0, -61.0) / vec2(124.0, 62.0) * main.size(),
+            );
+            widget.update(slot, context);
+        }
     }
 }
 
@@ -0,0 +1,91 @@
+use super::*;
+
+use geng_utils::bounded::Bounded;
+
+/// Time spent moving between the tiles, the rest of the move is spent standing still.
+const MOVE_ANIMATION_TIME: f32 = shared::TIME_PER_MOVE * 0.8;
+/// How far into the blocked tile a bouncing player gets, in tiles.
+const BOUNCE_DEPTH: f32 = 0.35;
+/// Peak height of a flying mushroom, in tiles.
+const ARC_HEIGHT: f32 = 0.3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TweenKind {
+    /// Walking or sliding onto the next tile.
+    Step,
+    /// Flying over the tiles, used by thrown mushrooms.
+    Arc,
+    /// Fading out and shimmering back in at the target.
+    Teleport,
+    /// Bumping into the target tile and returning.
+    Bounce,
+}
+
+/// Interpolated movement between two tiles during a resolution move.
+#[derive(Debug, Clone)]
+pub struct Tween {
+    pub kind: TweenKind,
+    /// World position of the tile the movement starts from.
+    pub from: vec2<FCoord>,
+    pub to: vec2<FCoord>,
+    pub lifetime: Bounded<FTime>,
+}
+
+impl Tween {
+    pub fn new(kind: TweenKind, from: vec2<FCoord>, to: vec2<FCoord>) -> Self {
+        Self {
+            kind,
+            from,
+            to,
+            lifetime: Bounded::new_max(r32(MOVE_ANIMATION_TIME)),
+        }
+    }
+
+    /// Progress of the animation from 0 to 1.
+    pub fn progress(&self) -> f32 {
+        1.0 - self.lifetime.get_ratio().as_f32()
+    }
+
+    /// Current world position of the tile corner.
+    pub fn position(&self) -> vec2<FCoord> {
+        let t = self.progress();
+        let t = match self.kind {
+            TweenKind::Step => t * t * (3.0 - 2.0 * t),
+            TweenKind::Arc => t,
+            TweenKind::Teleport => {
+                if t < 0.5 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            TweenKind::Bounce => BOUNCE_DEPTH * (t * std::f32::consts::PI).sin(),
+        };
+        self.from + (self.to - self.from) * r32(t)
+    }
+
+    /// Height above the ground, in tiles.
+    pub fn height(&self) -> f32 {
+        match self.kind {
+            TweenKind::Arc => {
+                let t = self.progress();
+                4.0 * ARC_HEIGHT * t * (1.0 - t)
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Opacity, teleporting fades out and back in.
+    pub fn alpha(&self) -> f32 {
+        match self.kind {
+            TweenKind::Teleport => (2.0 * self.progress() - 1.0).abs(),
+            _ => 1.0,
+        }
+    }
+
+    /// Where the tile is drawn at the current moment.
+    pub fn bounds(&self, cell_size: vec2<FCoord>) -> Aabb2<FCoord> {
+        let lift = vec2(FCoord::ZERO, cell_size.y * r32(self.height()));
+        Aabb2::point(self.position() + lift).extend_positive(cell_size)
+    }
+}
@@ -4,51 +4,160 @@ use super::*;
 
 use crate::{
     interop::{ClientId, ClientMessage, ServerMessage},
-    model::{particles::*, shared::GameEvent},
+    model::{animation::*, history::TurnHistory, particles::*, shared::GameEvent},
 };
 
+/// Camera zoom out limit, zoom `1` fits the whole map.
+const MIN_ZOOM: f32 = 0.5;
+/// Number of tiles across the screen at the highest zoom.
+const MIN_VISIBLE_TILES: f32 = 6.0;
+/// Maps wider than that many tiles start zoomed in on the player.
+const START_VISIBLE_TILES: f32 = 24.0;
+/// How quickly the camera catches up with the followed player.
+const CAMERA_FOLLOW_SPEED: f32 = 5.0;
+
 pub struct ClientModel {
     pub player_id: ClientId,
     pub messages: Vec<ClientMessage>,
     pub camera: Camera2d,
+    /// Size of the camera view at zoom `1`.
+    camera_size: vec2<f32>,
+    pub zoom: f32,
+    /// The player the camera is following.
+    pub follow: Option<ClientId>,
+    /// Whether the client is watching the game instead of playing.
+    pub spectating: bool,
+    /// Whether the spectator has asked to join as a player.
+    pub joining: bool,
+    /// Whether to show every player's planned move once they are revealed.
+    pub show_moves: bool,
     pub shared: shared::SharedModel,
     pub tile_variants: HashMap<vec2<ICoord>, usize>,
     pub spawn_particles: Vec<SpawnParticles>,
     pub particles: Vec<Particle>,
     pub floating_texts: Vec<FloatingText>,
+    /// Events of the resolution moves received from the server, not played yet.
+    pub received_events: Vec<GameEvent>,
+    /// What happened in the previous turns of the match.
+    pub history: Vec<TurnHistory>,
+    /// Movement animations of the players during resolution.
+    pub player_tweens: HashMap<ClientId, Tween>,
+    /// Movement animations of the flying mushrooms by the tile they are moving onto.
+    pub mushroom_tweens: HashMap<vec2<ICoord>, Tween>,
+    /// Tiles the player currently sees with fog of war.
+    pub vision: Option<TileGrid<bool>>,
+    pub fog_memory: FogMemory,
+    /// Teleport target selected with the keyboard, not yet confirmed.
+    pub key_cursor: Option<vec2<ICoord>>,
+    /// Tiles the player can reach this turn, kept until the model changes.
+    pub reachable: Option<ReachableOverlay>,
+}
+
+/// Tiles the player can reach by walking and, with sprint available, by sprinting.
+#[derive(Debug, Clone)]
+pub struct ReachableOverlay {
+    /// Position and walk and sprint speeds of the player the overlay was computed for.
+    key: (vec2<ICoord>, usize, Option<usize>),
+    pub walk: pathfind::Reachable,
+    pub sprint: Option<pathfind::Reachable>,
+}
+
+/// Last known information about the tiles hidden by fog of war.
+#[derive(Debug, Clone, Default)]
+pub struct FogMemory {
+    pub mushrooms: Vec<vec2<ICoord>>,
+    pub players: HashMap<ClientId, vec2<ICoord>>,
+    /// Owners of the territory tiles.
+    pub territory: HashMap<vec2<ICoord>, ClientId>,
 }
 
 impl ClientModel {
     pub fn new(player_id: ClientId, model: shared::SharedModel) -> Self {
         let map = model.map.world_bounds().as_f32();
-        Self {
+        let camera_size = map.size() * 1.8;
+        let start_zoom = (model.map.bounds.width() as f32 / START_VISIBLE_TILES).max(1.0);
+        let mut client = Self {
             player_id,
             messages: Vec::new(),
             camera: Camera2d {
                 center: map.center(),
                 rotation: Angle::ZERO,
                 fov: Camera2dFov::Cover {
-                    width: map.width() * 1.8,
-                    height: map.height() * 1.8,
+                    width: camera_size.x,
+                    height: camera_size.y,
                     scale: 1.0,
                 },
             },
+            camera_size,
+            zoom: 1.0,
+            follow: None,
+            spectating: false,
+            joining: false,
+            show_moves: false,
 
             spawn_particles: Vec::new(),
             particles: Vec::new(),
             floating_texts: Vec::new(),
+            received_events: Vec::new(),
+            history: Vec::new(),
+            player_tweens: HashMap::new(),
+            mushroom_tweens: HashMap::new(),
+
+            vision: None,
+            fog_memory: FogMemory::default(),
+            key_cursor: None,
+            reachable: None,
 
             tile_variants: HashMap::new(),
             shared: model,
+        };
+
+        // Start close to the player on large maps
+        client.zoom_camera(start_zoom);
+        if let Some(player) = client.shared.players.get(&player_id) {
+            client.camera.center = client.shared.map.tile_bounds(player.pos).as_f32().center();
+            client.clamp_camera();
         }
+        client
     }
 
     pub fn update(&mut self, delta_time: FTime) -> Vec<GameEvent> {
-        let events = self.shared.update(delta_time);
+        for tween in self
+            .player_tweens
+            .values_mut()
+            .chain(self.mushroom_tweens.values_mut())
+        {
+            tween.lifetime.change(-delta_time);
+        }
+        self.player_tweens
+            .retain(|_, tween| tween.lifetime.is_above_min());
+        self.mushroom_tweens
+            .retain(|_, tween| tween.lifetime.is_above_min());
+
+        // Resolution is played back from the server events instead of being simulated
+        let mut events = match self.shared.phase {
+            shared::Phase::Resolution { .. } => Vec::new(),
+            _ => self.shared.update(delta_time),
+        };
+        events.append(&mut self.received_events);
 
         for event in &events {
             self.process_event(event);
         }
+        if !events.is_empty() {
+            self.reachable = None;
+        }
+
+        if self.joining && self.shared.players.contains_key(&self.player_id) {
+            // Joined the match
+            self.spectating = false;
+            self.joining = false;
+            self.follow = None;
+        }
+
+        self.update_vision();
+        self.update_reachable();
+        self.update_camera(delta_time);
 
         for text in &mut self.floating_texts {
             text.lifetime.change(-delta_time);
@@ -73,18 +182,184 @@ impl ClientModel {
         events
     }
 
+    fn max_zoom(&self) -> f32 {
+        let tiles = self.camera_size.x / self.shared.map.cell_size.x.as_f32();
+        (tiles / MIN_VISIBLE_TILES).max(1.0)
+    }
+
+    /// Zooms the camera in by the factor, keeping it within the limits.
+    pub fn zoom_camera(&mut self, factor: f32) {
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, self.max_zoom());
+        self.camera.fov = Camera2dFov::Cover {
+            width: self.camera_size.x / self.zoom,
+            height: self.camera_size.y / self.zoom,
+            scale: 1.0,
+        };
+    }
+
+    /// Moves the camera by the offset in world coordinates and stops following.
+    pub fn pan_camera(&mut self, delta: vec2<f32>) {
+        self.camera.center += delta;
+        self.follow = None;
+        self.clamp_camera();
+    }
+
+    /// Centers the camera on the position in world coordinates and stops following.
+    pub fn move_camera(&mut self, center: vec2<f32>) {
+        self.camera.center = center;
+        self.follow = None;
+        self.clamp_camera();
+    }
+
+    /// Keeps the map on the screen.
+    fn clamp_camera(&mut self) {
+        let bounds = self.shared.map.world_bounds().as_f32();
+        let center = &mut self.camera.center;
+        center.x = center.x.clamp(bounds.min.x, bounds.max.x);
+        center.y = center.y.clamp(bounds.min.y, bounds.max.y);
+    }
+
+    fn update_camera(&mut self, delta_time: FTime) {
+        if let Some(player) = self.follow.and_then(|id| self.shared.players.get(&id)) {
+            let target = self.player_bounds(player).as_f32().center();
+            let t = (CAMERA_FOLLOW_SPEED * delta_time.as_f32()).min(1.0);
+            self.camera.center += (target - self.camera.center) * t;
+        }
+        self.clamp_camera();
+    }
+
+    /// Where the player is drawn, following the movement animation.
+    pub fn player_bounds(&self, player: &Player) -> Aabb2<FCoord> {
+        match self.player_tweens.get(&player.id) {
+            Some(tween) => tween.bounds(self.shared.map.cell_size),
+            None => self.shared.map.tile_bounds(player.pos),
+        }
+    }
+
+    /// Where the mushroom is drawn, following the flight animation.
+    pub fn mushroom_bounds(&self, mushroom: &shared::Mushroom) -> Aabb2<FCoord> {
+        match self.mushroom_tweens.get(&mushroom.position) {
+            Some(tween) => tween.bounds(self.shared.map.cell_size),
+            None => self.shared.map.tile_bounds(mushroom.position),
+        }
+    }
+
+    /// Checks whether the tile is hidden by fog of war.
+    pub fn is_fogged(&self, pos: vec2<ICoord>) -> bool {
+        self.vision
+            .as_ref()
+            .is_some_and(|vision| !vision.get(pos).copied().unwrap_or(false))
+    }
+
+    /// Searches the tiles the player can reach again
+    /// when the model or the player's position or speed changed.
+    fn update_reachable(&mut self) {
+        let Some(player) = self.shared.players.get(&self.player_id) else {
+            self.reachable = None;
+            return;
+        };
+        let sprint_speed = (player.cooldown_sprint <= 0
+            && player.customization.loadout.contains(Ability::Sprint))
+        .then(|| player.speed(true));
+        let key = (player.pos, player.speed(false), sprint_speed);
+        if self
+            .reachable
+            .as_ref()
+            .is_some_and(|overlay| overlay.key == key)
+        {
+            return;
+        }
+        let (pos, walk_speed, sprint_speed) = key;
+        self.reachable = Some(ReachableOverlay {
+            key,
+            walk: self.shared.reachable_tiles(pos, walk_speed),
+            sprint: sprint_speed.map(|speed| self.shared.reachable_tiles(pos, speed)),
+        });
+    }
+
+    fn update_vision(&mut self) {
+        let player = self.shared.players.get(&self.player_id);
+        let (true, Some(player)) = (self.shared.rules.fog_of_war, player) else {
+            self.vision = None;
+            return;
+        };
+        self.vision = Some(self.shared.visible_tiles(player.pos));
+
+        // Remember what is seen
+        let memory = &mut self.fog_memory;
+        let vision = self.vision.as_ref().unwrap();
+        let is_visible = |pos: vec2<ICoord>| vision.get(pos).copied().unwrap_or(false);
+        memory.mushrooms.retain(|&pos| !is_visible(pos));
+        memory.mushrooms.extend(
+            self.shared
+                .mushrooms
+                .iter()
+                .filter(|mushroom| mushroom.speed_left == 0)
+                .map(|mushroom| mushroom.position),
+        );
+        memory.players.retain(|_, &mut pos| !is_visible(pos));
+        memory.players.extend(
+            self.shared
+                .players
+                .values()
+                .filter(|player| player.id != self.player_id)
+                .map(|player| (player.id, player.pos)),
+        );
+        memory.territory.retain(|&pos, _| !is_visible(pos));
+        memory.territory.extend(
+            self.shared
+                .territory
+                .iter()
+                .filter_map(|(pos, owner)| owner.map(|owner| (pos, owner))),
+        );
+    }
+
     pub fn handle_message(&mut self, message: ServerMessage) {
+        self.reachable = None;
         match message {
             ServerMessage::Ping => {
                 self.messages.push(ClientMessage::Pong);
             }
             ServerMessage::Setup(_setup) => {}
-            ServerMessage::Sync(model) => self.shared = model,
-            ServerMessage::StartResolution(model) => self.shared = model,
-            ServerMessage::FinishResolution(model) => self.shared = model,
+            ServerMessage::Status(_status) => {}
+            ServerMessage::Sync(model) => {
+                self.shared = model;
+                self.player_tweens.clear();
+                self.mushroom_tweens.clear();
+            }
+            ServerMessage::StartResolution(model) => {
+                self.shared = model;
+                if !self.spectating
+                    && self.zoom > 1.0
+                    && let shared::Phase::Resolution { .. } = self.shared.phase
+                {
+                    // Keep the player in sight while the moves play out
+                    self.follow = Some(self.player_id);
+                }
+                self.key_cursor = None;
+            }
+            ServerMessage::ResolveMove(model, events) => {
+                // Recorded right away, the turn may be over by the time they are played
+                for event in &events {
+                    history::record(&mut self.history, model.turn_current, event);
+                }
+                self.shared = model;
+                self.received_events.extend(events);
+            }
+            ServerMessage::FinishResolution(model) => {
+                self.shared = model;
+                if !self.spectating && self.follow == Some(self.player_id) {
+                    self.follow = None;
+                }
+            }
             ServerMessage::PlayerCustomization(player, customization) => {
-                if let Some(player) = self.shared.players.get_mut(&player) {
-                    player.customization = customization;
+                if let Some(player) = self
+                    .shared
+                    .players
+                    .get_mut(&player)
+                    .or_else(|| self.shared.hidden_players.get_mut(&player))
+                {
+                    player.set_customization(customization, &self.shared.rules);
                 }
             }
         }
@@ -92,7 +367,7 @@ impl ClientModel {
 
     fn process_event(&mut self, event: &GameEvent) {
         match *event {
-            GameEvent::MushroomPickup(pos) => self.spawn_particles.push(SpawnParticles {
+            GameEvent::MushroomPickup(_, pos) => self.spawn_particles.push(SpawnParticles {
                 kind: ParticleKind::Mushroom,
                 distribution: ParticleDistribution::Circle {
                     center: self.shared.map.to_world_center(pos),
@@ -100,7 +375,7 @@ impl ClientModel {
                 },
                 ..default()
             }),
-            GameEvent::MushroomsCollected(pos, n) => self.spawn_particles.push(SpawnParticles {
+            GameEvent::MushroomsCollected(_, pos, n) => self.spawn_particles.push(SpawnParticles {
                 kind: ParticleKind::Mushroom,
                 density: r32(3.0 + 1.5 * n as f32),
                 distribution: ParticleDistribution::Circle {
@@ -109,7 +384,7 @@ impl ClientModel {
                 },
                 ..default()
             }),
-            GameEvent::Score(score, pos) => self.floating_texts.push(FloatingText {
+            GameEvent::Score(_, score, pos) => self.floating_texts.push(FloatingText {
                 text: format!("+{score}").into(),
                 position: self.shared.map.to_world_center(pos),
                 velocity: vec2(0.1, 0.5).as_r32(),
@@ -117,7 +392,7 @@ impl ClientModel {
                 color: Rgba::try_from("#E5BD85").unwrap(),
                 lifetime: Bounded::new_max(r32(1.0)),
             }),
-            GameEvent::PlayerStunned(_, pos) => self.spawn_particles.push(SpawnParticles {
+            GameEvent::PlayerStunned(_, pos, _) => self.spawn_particles.push(SpawnParticles {
                 kind: ParticleKind::Stun,
                 density: r32(5.0),
                 distribution: ParticleDistribution::Circle {
@@ -126,6 +401,70 @@ impl ClientModel {
                 },
                 ..default()
             }),
+            GameEvent::PlayerMoved(player_id, from, to) => {
+                let map = &self.shared.map;
+                // Slides continue the step started in the same move
+                let start = match self.player_tweens.get(&player_id) {
+                    Some(tween) if tween.kind == TweenKind::Step && tween.progress() == 0.0 => {
+                        tween.from
+                    }
+                    _ => map.to_world(from),
+                };
+                let kind = if shared::are_adjacent(from, to) {
+                    TweenKind::Step
+                } else {
+                    TweenKind::Teleport
+                };
+                self.player_tweens
+                    .insert(player_id, Tween::new(kind, start, map.to_world(to)));
+            }
+            GameEvent::PlayerPushed(player_id, from, to) => {
+                let map = &self.shared.map;
+                self.player_tweens.insert(
+                    player_id,
+                    Tween::new(TweenKind::Step, map.to_world(from), map.to_world(to)),
+                );
+            }
+            GameEvent::PlayerBounced(player_id, toward) => {
+                if let Some(player) = self.shared.players.get(&player_id)
+                    && shared::are_adjacent(player.pos, toward)
+                {
+                    let map = &self.shared.map;
+                    self.player_tweens.insert(
+                        player_id,
+                        Tween::new(
+                            TweenKind::Bounce,
+                            map.to_world(player.pos),
+                            map.to_world(toward),
+                        ),
+                    );
+                }
+            }
+            GameEvent::MushroomMoved(from, to) => {
+                let map = &self.shared.map;
+                self.mushroom_tweens.insert(
+                    to,
+                    Tween::new(TweenKind::Arc, map.to_world(from), map.to_world(to)),
+                );
+            }
+            GameEvent::WallBuilt(pos)
+            | GameEvent::HazardSpawned(pos)
+            | GameEvent::BoulderCrumbled(pos) => self.spawn_particles.push(SpawnParticles {
+                kind: ParticleKind::Stun,
+                density: r32(5.0),
+                distribution: ParticleDistribution::Aabb(self.shared.map.tile_bounds(pos)),
+                ..default()
+            }),
+            GameEvent::MushroomRain(ref positions) => {
+                for &pos in positions {
+                    self.spawn_particles.push(SpawnParticles {
+                        kind: ParticleKind::Mushroom,
+                        distribution: ParticleDistribution::Aabb(self.shared.map.tile_bounds(pos)),
+                        velocity: vec2(0.0, -1.0).as_r32(),
+                        ..default()
+                    });
+                }
+            }
             _ => {}
         }
     }
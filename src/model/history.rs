@@ -0,0 +1,160 @@
+use super::*;
+
+use shared::{GameEvent, SharedModel, StunCause};
+
+/// What happened during one turn's resolution.
+#[derive(Debug, Clone)]
+pub struct TurnHistory {
+    pub turn: Turns,
+    pub entries: Vec<HistoryEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub kind: HistoryKind,
+    /// Tiles involved, highlighted on the board when hovered.
+    pub tiles: Vec<vec2<ICoord>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum HistoryKind {
+    Moved(ClientId),
+    Teleported(ClientId),
+    Pushed(ClientId),
+    Stunned(ClientId, StunCause),
+    Pickup(ClientId),
+    Throw(ClientId),
+    Scored(ClientId, Score),
+    WallBuilt,
+    BoulderRolled,
+    BoulderCrumbled,
+    MushroomRain,
+}
+
+/// A row of the history panel.
+pub enum HistoryLine<'a> {
+    Turn(Turns),
+    Entry(&'a HistoryEntry),
+}
+
+/// Adds the event to the history of the turn it happened in.
+pub fn record(history: &mut Vec<TurnHistory>, turn: Turns, event: &GameEvent) {
+    if event.player().is_none() && event.positions().is_empty() {
+        return; // Phase changes
+    }
+    if history.last().is_some_and(|last| last.turn > turn) {
+        // A new game has started
+        history.clear();
+    }
+    if history.last().is_none_or(|last| last.turn != turn) {
+        history.push(TurnHistory {
+            turn,
+            entries: Vec::new(),
+        });
+    }
+    let entries = &mut history.last_mut().unwrap().entries;
+
+    let (kind, tiles) = match *event {
+        GameEvent::PlayerMoved(player_id, from, to) => {
+            if !shared::are_adjacent(from, to) {
+                return; // Recorded from the teleport event
+            }
+            // Join the steps into one entry
+            if let Some(entry) = entries
+                .iter_mut()
+                .find(|entry| matches!(entry.kind, HistoryKind::Moved(id) if id == player_id))
+            {
+                entry.tiles.push(to);
+                return;
+            }
+            (HistoryKind::Moved(player_id), vec![from, to])
+        }
+        GameEvent::MushroomMoved(from, to) => {
+            // Follow the flight of the thrown mushroom
+            if let Some(entry) = entries.iter_mut().find(|entry| {
+                matches!(entry.kind, HistoryKind::Throw(_)) && entry.tiles.last() == Some(&from)
+            }) {
+                entry.tiles.push(to);
+            }
+            return;
+        }
+        GameEvent::Teleport(player_id, to) => (HistoryKind::Teleported(player_id), vec![to]),
+        GameEvent::PlayerPushed(player_id, from, to) => {
+            (HistoryKind::Pushed(player_id), vec![from, to])
+        }
+        GameEvent::PlayerStunned(player_id, pos, cause) => {
+            (HistoryKind::Stunned(player_id, cause), vec![pos])
+        }
+        GameEvent::MushroomPickup(player_id, pos) => (HistoryKind::Pickup(player_id), vec![pos]),
+        GameEvent::MushroomThrow(player_id, pos) => (HistoryKind::Throw(player_id), vec![pos]),
+        GameEvent::Score(player_id, score, pos) => {
+            (HistoryKind::Scored(player_id, score), vec![pos])
+        }
+        GameEvent::WallBuilt(pos) => (HistoryKind::WallBuilt, vec![pos]),
+        GameEvent::HazardSpawned(pos) => (HistoryKind::BoulderRolled, vec![pos]),
+        GameEvent::BoulderCrumbled(pos) => (HistoryKind::BoulderCrumbled, vec![pos]),
+        GameEvent::MushroomRain(ref positions) => (HistoryKind::MushroomRain, positions.clone()),
+        _ => return,
+    };
+    entries.push(HistoryEntry { kind, tiles });
+}
+
+/// Rows of the history panel, the latest turn first.
+pub fn lines(history: &[TurnHistory]) -> Vec<HistoryLine<'_>> {
+    history
+        .iter()
+        .rev()
+        .flat_map(|turn| {
+            std::iter::once(HistoryLine::Turn(turn.turn))
+                .chain(turn.entries.iter().map(HistoryLine::Entry))
+        })
+        .collect()
+}
+
+impl HistoryEntry {
+    /// The player the entry is about.
+    pub fn player(&self) -> Option<ClientId> {
+        match self.kind {
+            HistoryKind::Moved(player_id)
+            | HistoryKind::Teleported(player_id)
+            | HistoryKind::Pushed(player_id)
+            | HistoryKind::Stunned(player_id, _)
+            | HistoryKind::Pickup(player_id)
+            | HistoryKind::Throw(player_id)
+            | HistoryKind::Scored(player_id, _) => Some(player_id),
+            _ => None,
+        }
+    }
+
+    pub fn describe(&self, model: &SharedModel) -> String {
+        let name = self
+            .player()
+            .and_then(|player_id| model.all_players().find(|player| player.id == player_id))
+            .map_or("Someone", |player| player.customization.name.as_str());
+        match self.kind {
+            HistoryKind::Moved(_) => {
+                let steps = self.tiles.len() - 1;
+                let s = if steps == 1 { "" } else { "s" };
+                format!("{name} moved {steps} tile{s}")
+            }
+            HistoryKind::Teleported(_) => format!("{name} teleported"),
+            HistoryKind::Pushed(_) => format!("{name} got pushed"),
+            HistoryKind::Stunned(_, cause) => {
+                let cause = match cause {
+                    StunCause::Mushroom => "a mushroom",
+                    StunCause::Hazard => "a hazard",
+                    StunCause::Trail => "a trail",
+                    StunCause::Collision => "a collision",
+                };
+                format!("{name} got stunned by {cause}")
+            }
+            HistoryKind::Pickup(_) => format!("{name} picked up a mushroom"),
+            HistoryKind::Throw(_) => format!("{name} threw a mushroom"),
+            HistoryKind::Scored(_, score) => format!("{name} scored {score}"),
+            HistoryKind::WallBuilt => "A wall was built".to_owned(),
+            HistoryKind::BoulderRolled => "A boulder rolled in".to_owned(),
+            HistoryKind::BoulderCrumbled => "A boulder crumbled".to_owned(),
+            HistoryKind::MushroomRain => "Mushrooms rained down".to_owned(),
+        }
+    }
+}
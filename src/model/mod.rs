@@ -1,10 +1,13 @@
+pub mod animation;
 pub mod client;
+pub mod history;
 pub mod particles;
+pub mod pathfind;
 pub mod shared;
 
 use crate::interop::ClientId;
 
-use geng::prelude::*;
+use geng::prelude::{itertools::Itertools, *};
 use geng_utils::conversions::*;
 
 pub type ICoord = i64;
@@ -13,22 +16,135 @@ pub type FTime = R32;
 pub type Turns = i64;
 pub type Score = u64;
 
+pub const LOADOUT_SIZE: usize = 3;
+
+/// Game rules chosen by the server.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, clap::Args)]
+pub struct Rules {
+    /// Give each character individual stats and a passive trait.
+    #[clap(long)]
+    pub asymmetric: bool,
+    /// How many turns trails stay on the map, 0 clears them every turn.
+    #[clap(long, default_value_t = 0)]
+    pub trail_duration: Turns,
+    /// Trails from previous turns block movement instead of stunning.
+    #[clap(long)]
+    pub trail_blocks: bool,
+    /// Players paint the tiles they walk over and score for the owned area.
+    #[clap(long)]
+    pub territory: bool,
+    /// Add moving hazards and random environmental events.
+    #[clap(long)]
+    pub hazards: bool,
+    /// Players only see the tiles within their vision radius.
+    #[clap(long)]
+    pub fog_of_war: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Terrain {
+    #[default]
+    Floor,
+    /// Costs two speed to enter.
+    Mud,
+    /// Makes players slide one extra tile in the direction of movement.
+    Ice,
+    /// Blocks walking, but not thrown mushrooms.
+    Water,
+    /// Hides the players standing in it from others.
+    Bush,
+}
+
+impl Terrain {
+    pub fn is_walkable(self) -> bool {
+        self != Terrain::Water
+    }
+
+    /// Speed spent to step onto the tile.
+    pub fn move_cost(self) -> usize {
+        match self {
+            Terrain::Mud => 2,
+            _ => 1,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Map {
     pub bounds: Aabb2<ICoord>,
     pub cell_size: vec2<FCoord>,
     pub walls: Vec<vec2<ICoord>>,
+    pub terrain: TileGrid<Terrain>,
+    /// Base positions, the center of the map is used if empty.
+    pub bases: Vec<vec2<ICoord>>,
 }
 
 impl Map {
     pub fn new(size: vec2<ICoord>) -> Self {
+        let bounds = Aabb2::from_corners(-size / 2 - size.map(|x| x % 2 - 1), size / 2);
         Self {
-            bounds: Aabb2::from_corners(-size / 2 - size.map(|x| x % 2 - 1), size / 2),
+            bounds,
             cell_size: vec2::splat(FCoord::ONE),
             walls: Vec::new(),
+            terrain: TileGrid::new(bounds, Terrain::Floor),
+            bases: Vec::new(),
         }
     }
 
+    /// Parses a map with one character per tile, starting from the top row:
+    /// `.` floor, `#` wall, `B` base, `,` mud, `_` ice, `~` water, `*` bush.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let rows: Vec<&str> = text
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty())
+            .collect();
+        let width = rows
+            .iter()
+            .map(|row| row.chars().count())
+            .max()
+            .unwrap_or(0);
+        if width == 0 {
+            anyhow::bail!("the map is empty");
+        }
+
+        let mut map = Self::new(vec2(width, rows.len()).map(|x| x as ICoord));
+        for (row_i, row) in rows.iter().enumerate() {
+            for (col_i, tile) in row.chars().enumerate() {
+                let pos = vec2(
+                    map.bounds.min.x + col_i as ICoord,
+                    map.bounds.max.y - row_i as ICoord,
+                );
+                let terrain = match tile {
+                    '.' => Terrain::Floor,
+                    '#' => {
+                        map.walls.push(pos);
+                        Terrain::Floor
+                    }
+                    'B' => {
+                        map.bases.push(pos);
+                        Terrain::Floor
+                    }
+                    ',' => Terrain::Mud,
+                    '_' => Terrain::Ice,
+                    '~' => Terrain::Water,
+                    '*' => Terrain::Bush,
+                    _ => anyhow::bail!(
+                        "unknown tile {tile:?} at line {} column {}",
+                        row_i + 1,
+                        col_i + 1
+                    ),
+                };
+                map.terrain.set(pos, terrain);
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn terrain(&self, pos: vec2<ICoord>) -> Terrain {
+        self.terrain.get(pos).copied().unwrap_or_default()
+    }
+
     pub fn to_world(&self, pos: vec2<ICoord>) -> vec2<FCoord> {
         self.cell_size * pos.as_r32()
     }
@@ -75,6 +191,67 @@ impl Map {
     }
 }
 
+/// A value for every tile inside the bounds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TileGrid<T> {
+    bounds: Aabb2<ICoord>,
+    tiles: Vec<T>,
+}
+
+impl<T: Clone> TileGrid<T> {
+    pub fn new(bounds: Aabb2<ICoord>, value: T) -> Self {
+        let width = bounds.max.x - bounds.min.x + 1;
+        let height = bounds.max.y - bounds.min.y + 1;
+        Self {
+            bounds,
+            tiles: vec![value; (width * height).max(0) as usize],
+        }
+    }
+
+    pub fn fill(&mut self, value: T) {
+        self.tiles.fill(value);
+    }
+}
+
+impl<T> TileGrid<T> {
+    fn index(&self, pos: vec2<ICoord>) -> Option<usize> {
+        let bounds = &self.bounds;
+        if pos.x < bounds.min.x
+            || pos.x > bounds.max.x
+            || pos.y < bounds.min.y
+            || pos.y > bounds.max.y
+        {
+            return None;
+        }
+        let width = bounds.max.x - bounds.min.x + 1;
+        Some(((pos.y - bounds.min.y) * width + pos.x - bounds.min.x) as usize)
+    }
+
+    pub fn get(&self, pos: vec2<ICoord>) -> Option<&T> {
+        self.index(pos).and_then(|i| self.tiles.get(i))
+    }
+
+    pub fn get_mut(&mut self, pos: vec2<ICoord>) -> Option<&mut T> {
+        self.index(pos).and_then(|i| self.tiles.get_mut(i))
+    }
+
+    /// Sets the value of the tile, ignores positions out of bounds.
+    pub fn set(&mut self, pos: vec2<ICoord>, value: T) {
+        if let Some(tile) = self.get_mut(pos) {
+            *tile = value;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (vec2<ICoord>, &T)> {
+        let min = self.bounds.min;
+        let width = self.bounds.max.x - min.x + 1;
+        self.tiles.iter().enumerate().map(move |(i, tile)| {
+            let i = i as ICoord;
+            (min + vec2(i % width, i / width), tile)
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Character {
     Ant,
@@ -125,6 +302,50 @@ impl Character {
         *Character::all().choose(&mut thread_rng()).unwrap()
     }
 
+    /// Stats used with the asymmetric rules.
+    pub fn stats(&self) -> CharacterStats {
+        let (max_speed, capacity, stun_resistance, teleport_range) = match self {
+            Character::Ant => (4, 5, 0, 3),
+            Character::Bunny => (6, 3, 0, 3),
+            Character::Cat => (5, 4, 0, 4),
+            Character::Crab => (4, 4, 1, 2),
+            Character::Dinosaur => (4, 5, 1, 3),
+            Character::Dog => (5, 4, 0, 3),
+            Character::Elephant => (3, 5, 1, 2),
+            Character::Fishman => (5, 4, 0, 4),
+            Character::Fox => (6, 3, 0, 3),
+            Character::Frog => (5, 3, 0, 5),
+            Character::Ghost => (5, 3, 0, 6),
+            Character::Goat => (5, 4, 1, 3),
+            Character::Mouse => (6, 3, 0, 3),
+            Character::Panda => (4, 5, 1, 3),
+            Character::Penguin => (4, 4, 0, 3),
+            Character::Skeleton => (5, 3, 1, 4),
+            Character::Snake => (5, 4, 0, 4),
+            Character::Unicorn => (5, 3, 0, 5),
+        };
+        CharacterStats {
+            max_speed,
+            mushroom_capacity: Some(capacity),
+            stun_resistance,
+            teleport_range,
+        }
+    }
+
+    /// Passive trait used with the asymmetric rules.
+    pub fn passive(&self) -> Passive {
+        match self {
+            Character::Ant | Character::Elephant => Passive::Sturdy,
+            Character::Bunny | Character::Dog | Character::Frog => Passive::Nimble,
+            Character::Cat | Character::Fishman | Character::Fox | Character::Ghost => {
+                Passive::Slippery
+            }
+            Character::Dinosaur | Character::Goat | Character::Snake => Passive::StrongArm,
+            Character::Mouse | Character::Panda | Character::Unicorn => Passive::Gourmet,
+            Character::Crab | Character::Penguin | Character::Skeleton => Passive::Builder,
+        }
+    }
+
     pub fn color(&self) -> Rgba<f32> {
         match self {
             Character::Ant => Rgba::try_from("#6D767B").unwrap(),
@@ -149,12 +370,64 @@ impl Character {
     }
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CharacterStats {
+    pub max_speed: usize,
+    /// How many mushrooms can be carried at once, unlimited if `None`.
+    pub mushroom_capacity: Option<usize>,
+    /// Reduces the duration of every stun.
+    pub stun_resistance: Turns,
+    pub teleport_range: ICoord,
+}
+
+impl Default for CharacterStats {
+    fn default() -> Self {
+        Self {
+            max_speed: 5,
+            mushroom_capacity: None,
+            stun_resistance: 0,
+            teleport_range: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Passive {
+    /// Carried mushrooms do not slow down.
+    Sturdy,
+    /// Sprint recharges one turn faster.
+    Nimble,
+    /// Does not drop mushrooms when stunned.
+    Slippery,
+    /// Thrown mushrooms fly further.
+    StrongArm,
+    /// Delivered mushrooms are worth an extra point.
+    Gourmet,
+    /// Built walls stand for an extra turn.
+    Builder,
+}
+
+impl Passive {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Passive::Sturdy => "sturdy",
+            Passive::Nimble => "nimble",
+            Passive::Slippery => "slippery",
+            Passive::StrongArm => "strong arm",
+            Passive::Gourmet => "gourmet",
+            Passive::Builder => "builder",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlayerTrail {
     pub player: ClientId,
     pub pos: vec2<ICoord>,
     pub connection_from: Option<vec2<ICoord>>,
     pub connection_to: vec2<ICoord>,
+    /// Turns until the trail disappears.
+    pub turns_left: Turns,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -170,6 +443,9 @@ pub enum PlayerMove {
     Throw {
         direction: vec2<ICoord>,
     },
+    BuildWall {
+        direction: vec2<ICoord>,
+    },
 }
 
 impl Default for PlayerMove {
@@ -181,11 +457,80 @@ impl Default for PlayerMove {
     }
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Ability {
+    Sprint,
+    Teleport,
+    Throw,
+    Wall,
+}
+
+impl Ability {
+    pub fn all() -> [Self; 4] {
+        [Self::Sprint, Self::Teleport, Self::Throw, Self::Wall]
+    }
+
+    /// The ability that has to be in the loadout to make the move.
+    pub fn required_by(player_move: &PlayerMove) -> Option<Self> {
+        match player_move {
+            PlayerMove::Normal { sprint, .. } => sprint.then_some(Self::Sprint),
+            PlayerMove::TeleportChanneling | PlayerMove::TeleportActivate { .. } => {
+                Some(Self::Teleport)
+            }
+            PlayerMove::Throw { .. } => Some(Self::Throw),
+            PlayerMove::BuildWall { .. } => Some(Self::Wall),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Loadout {
+    pub abilities: [Ability; LOADOUT_SIZE],
+}
+
+impl Default for Loadout {
+    fn default() -> Self {
+        Self {
+            abilities: [Ability::Sprint, Ability::Teleport, Ability::Throw],
+        }
+    }
+}
+
+impl Loadout {
+    pub fn contains(&self, ability: Ability) -> bool {
+        self.abilities.contains(&ability)
+    }
+
+    /// A valid loadout has no duplicate abilities.
+    pub fn is_valid(&self) -> bool {
+        self.abilities.iter().all_unique()
+    }
+
+    /// Replaces the ability in the slot with the next one not yet in the loadout.
+    pub fn cycle_slot(&mut self, slot: usize) {
+        let Some(&current) = self.abilities.get(slot) else {
+            return;
+        };
+        let all = Ability::all();
+        let current_i = all
+            .iter()
+            .position(|&ability| ability == current)
+            .unwrap_or(0);
+        if let Some(next) = (1..all.len())
+            .map(|offset| all[(current_i + offset) % all.len()])
+            .find(|&ability| !self.contains(ability))
+        {
+            self.abilities[slot] = next;
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlayerCustomization {
     pub name: String,
     pub character: Character,
     pub color: Rgba<f32>,
+    pub loadout: Loadout,
 }
 
 impl PlayerCustomization {
@@ -194,6 +539,7 @@ impl PlayerCustomization {
             name: "".into(),
             character: Character::random(),
             color: Rgba::try_from("#6d767b").unwrap(),
+            loadout: Loadout::default(),
         }
     }
 }
@@ -204,41 +550,177 @@ pub struct Player {
     pub score: Score,
     pub customization: PlayerCustomization,
     pub pos: vec2<ICoord>,
-    pub max_speed: usize,
+    pub stats: CharacterStats,
+    pub passive: Option<Passive>,
     pub submitted_move: PlayerMove,
     /// Currently carrying mushrooms.
     pub mushrooms: usize,
     pub stunned_duration: Option<Turns>,
     /// Speed at the start of resolution phase.
     pub resolution_speed_max: usize,
+    /// Score at the start of resolution phase.
+    pub resolution_score: Score,
     /// Remaining speed at resolution phase.
     pub resolution_speed_left: usize,
+    /// Index of the current tile in the submitted path.
+    pub resolution_path_index: usize,
+    /// Tiles walked at resolution phase, not counting the slides on ice.
+    pub resolution_steps: usize,
+    /// Waiting a move to step into mud.
+    pub is_wading: bool,
     pub cooldown_sprint: Turns,
     pub cooldown_teleport: Turns,
+    pub cooldown_wall: Turns,
     /// Teleport channeling state.
     pub is_channeling: bool,
+    pub match_stats: MatchStats,
+}
+
+/// What the player has done over the match, shown on the results screen.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MatchStats {
+    pub mushrooms_picked: usize,
+    pub mushrooms_delivered: usize,
+    pub mushrooms_thrown: usize,
+    /// Other players stunned by the thrown mushrooms.
+    pub hits_landed: usize,
+    pub times_stunned: usize,
+    /// Tiles walked, not counting the slides on ice.
+    pub tiles_walked: usize,
+    pub teleports: usize,
+    /// Most tiles walked in a single sprint.
+    pub longest_sprint: usize,
+}
+
+/// Title given to the player with the best statistic of the match.
+#[derive(Debug, Clone)]
+pub struct Award {
+    pub title: &'static str,
+    pub player: ClientId,
+    pub value: usize,
+}
+
+impl MatchStats {
+    /// Picks the best player for every award, awards nobody has earned are skipped.
+    pub fn awards<'a>(players: impl IntoIterator<Item = &'a Player>) -> Vec<Award> {
+        let players: Vec<&Player> = players
+            .into_iter()
+            .sorted_by_key(|player| player.id)
+            .collect();
+        let awards: [(&'static str, fn(&MatchStats) -> usize); 5] = [
+            ("Most mushroom hits", |stats| stats.hits_landed),
+            ("Longest sprint", |stats| stats.longest_sprint),
+            ("Top gatherer", |stats| stats.mushrooms_delivered),
+            ("Most stunned", |stats| stats.times_stunned),
+            ("Frequent flyer", |stats| stats.teleports),
+        ];
+        awards
+            .into_iter()
+            .filter_map(|(title, stat)| {
+                let player = players
+                    .iter()
+                    .rev()
+                    .max_by_key(|player| stat(&player.match_stats))?;
+                let value = stat(&player.match_stats);
+                (value > 0).then_some(Award {
+                    title,
+                    player: player.id,
+                    value,
+                })
+            })
+            .collect()
+    }
 }
 
 impl Player {
-    pub fn new(id: ClientId, customization: PlayerCustomization, pos: vec2<ICoord>) -> Self {
-        Self {
+    pub fn new(
+        id: ClientId,
+        customization: PlayerCustomization,
+        pos: vec2<ICoord>,
+        rules: &Rules,
+    ) -> Self {
+        let mut player = Self {
             id,
             score: 0,
-            customization,
+            customization: customization.clone(),
             pos,
-            max_speed: 5,
+            stats: CharacterStats::default(),
+            passive: None,
             submitted_move: PlayerMove::default(),
             mushrooms: 0,
             stunned_duration: None,
             resolution_speed_max: 0,
+            resolution_score: 0,
             resolution_speed_left: 0,
+            resolution_path_index: 0,
+            resolution_steps: 0,
+            is_wading: false,
             cooldown_sprint: 0,
             cooldown_teleport: 0,
+            cooldown_wall: 0,
             is_channeling: false,
+            match_stats: MatchStats::default(),
+        };
+        player.set_customization(customization, rules);
+        player
+    }
+
+    /// Updates the customization along with the character's stats.
+    pub fn set_customization(&mut self, customization: PlayerCustomization, rules: &Rules) {
+        if rules.asymmetric {
+            self.stats = customization.character.stats();
+            self.passive = Some(customization.character.passive());
+        } else {
+            self.stats = CharacterStats::default();
+            self.passive = None;
         }
+        self.customization = customization;
     }
 
     pub fn speed(&self, sprint: bool) -> usize {
-        self.max_speed.saturating_sub(self.mushrooms).max(1) + if sprint { 3 } else { 0 }
+        let slowdown = if self.passive == Some(Passive::Sturdy) {
+            0
+        } else {
+            self.mushrooms
+        };
+        self.stats.max_speed.saturating_sub(slowdown).max(1) + if sprint { 3 } else { 0 }
+    }
+
+    pub fn can_carry_more(&self) -> bool {
+        self.stats
+            .mushroom_capacity
+            .is_none_or(|capacity| self.mushrooms < capacity)
+    }
+
+    pub fn sprint_cooldown(&self) -> Turns {
+        if self.passive == Some(Passive::Nimble) {
+            shared::SPRINT_COOLDOWN - 1
+        } else {
+            shared::SPRINT_COOLDOWN
+        }
+    }
+
+    pub fn throw_speed(&self) -> usize {
+        if self.passive == Some(Passive::StrongArm) {
+            shared::THROW_SPEED + 2
+        } else {
+            shared::THROW_SPEED
+        }
+    }
+
+    pub fn score_per_mushroom(&self) -> Score {
+        if self.passive == Some(Passive::Gourmet) {
+            shared::SCORE_PER_MUSHROOM + 1
+        } else {
+            shared::SCORE_PER_MUSHROOM
+        }
+    }
+
+    pub fn wall_duration(&self) -> Turns {
+        if self.passive == Some(Passive::Builder) {
+            shared::WALL_DURATION + 1
+        } else {
+            shared::WALL_DURATION
+        }
     }
 }
@@ -0,0 +1,431 @@
+use super::{shared::SharedModel, *};
+
+use crate::interop::ClientId;
+
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+/// Tiles reachable within a speed budget and the shortest paths to them.
+#[derive(Debug, Clone)]
+pub struct Reachable {
+    pub start: vec2<ICoord>,
+    /// Speed spent to reach the tile.
+    pub costs: HashMap<vec2<ICoord>, usize>,
+    /// The tile each step was taken from.
+    previous: HashMap<vec2<ICoord>, vec2<ICoord>>,
+}
+
+impl Reachable {
+    pub fn cost(&self, pos: vec2<ICoord>) -> Option<usize> {
+        self.costs.get(&pos).copied()
+    }
+
+    pub fn contains(&self, pos: vec2<ICoord>) -> bool {
+        self.costs.contains_key(&pos)
+    }
+
+    /// The shortest path from the start to the tile, including both ends.
+    pub fn path_to(&self, target: vec2<ICoord>) -> Option<Vec<vec2<ICoord>>> {
+        if !self.contains(target) {
+            return None;
+        }
+
+        let mut path = vec![target];
+        let mut pos = target;
+        while pos != self.start {
+            let from = *self.previous.get(&pos)?;
+            let delta = pos - from;
+            if delta.x.abs() + delta.y.abs() > 1 {
+                // Slide on ice
+                path.push(from + delta / 2);
+            }
+            path.push(from);
+            pos = from;
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// Checks whether the shortest path to the tile passes over the other one,
+    /// including the ice tiles slid over.
+    fn passes_over(&self, target: vec2<ICoord>, tile: vec2<ICoord>) -> bool {
+        let mut pos = target;
+        loop {
+            if pos == tile {
+                return true;
+            }
+            let Some(&from) = self.previous.get(&pos) else {
+                return false;
+            };
+            let delta = pos - from;
+            if delta.x.abs() + delta.y.abs() > 1 && from + delta / 2 == tile {
+                return true;
+            }
+            pos = from;
+        }
+    }
+}
+
+impl Map {
+    /// Adjacent tiles inside the map bounds.
+    pub fn neighbours(&self, pos: vec2<ICoord>) -> impl Iterator<Item = vec2<ICoord>> + '_ {
+        [vec2(1, 0), vec2(-1, 0), vec2(0, 1), vec2(0, -1)]
+            .into_iter()
+            .map(move |dir| pos + dir)
+            .filter(|&pos| self.is_in_bounds(pos))
+    }
+}
+
+impl SharedModel {
+    /// Steps that can be taken from the tile following the same rules as `validate_path`.
+    /// Stepping onto ice includes the forced slide, returns the destination and the cost.
+    fn path_steps(&self, from: vec2<ICoord>) -> impl Iterator<Item = (vec2<ICoord>, usize)> + '_ {
+        self.map
+            .neighbours(from)
+            .filter(|&to| self.is_walkable(to) && !self.is_blocked_by_trail(to))
+            .map(move |to| {
+                let cost = self.map.terrain(to).move_cost();
+                (self.ice_slide(from, to).unwrap_or(to), cost)
+            })
+    }
+
+    /// Finds every tile reachable from the start within the speed budget.
+    pub fn reachable_tiles(&self, start: vec2<ICoord>, speed: usize) -> Reachable {
+        self.search(start, speed, None)
+    }
+
+    /// Finds the shortest valid path between the tiles within the speed budget.
+    pub fn find_path(
+        &self,
+        start: vec2<ICoord>,
+        target: vec2<ICoord>,
+        speed: usize,
+    ) -> Option<Vec<vec2<ICoord>>> {
+        self.search(start, speed, Some(target)).path_to(target)
+    }
+
+    /// Edits the planned path one step at a time: steps onto the tile,
+    /// or cancels the last step if the tile is the previous one in the path.
+    /// Returns whether the path has changed.
+    pub fn step_path(
+        &self,
+        player_id: ClientId,
+        path: &mut Vec<vec2<ICoord>>,
+        pos: vec2<ICoord>,
+    ) -> bool {
+        let Some(player) = self.players.get(&player_id) else {
+            return false;
+        };
+        let sprint = matches!(
+            player.submitted_move,
+            PlayerMove::Normal { sprint: true, .. }
+        );
+
+        if path
+            .len()
+            .checked_sub(2)
+            .and_then(|i| path.get(i))
+            .is_some_and(|&prev_pos| prev_pos == pos)
+        {
+            // Cancel last move
+            self.undo_step(path);
+            return true;
+        }
+
+        if path.contains(&pos)
+            || !path
+                .last()
+                .is_some_and(|&last| shared::are_adjacent(last, pos))
+            || !self.is_walkable(pos)
+            || self.is_blocked_by_trail(pos)
+        {
+            return false;
+        }
+
+        // Add tile, sliding further on ice
+        let mut new_path = path.clone();
+        new_path.push(pos);
+        if let Some((_, Some(slide))) = self.walk_path(&new_path)
+            && !new_path.contains(&slide)
+        {
+            new_path.push(slide);
+        }
+        if let Some((cost, None)) = self.walk_path(&new_path)
+            && cost <= player.speed(sprint)
+        {
+            *path = new_path;
+            return true;
+        }
+        false
+    }
+
+    /// Removes the last step of the path, together with the step onto ice before a slide.
+    pub fn undo_step(&self, path: &mut Vec<vec2<ICoord>>) {
+        if path.len() <= 1 {
+            return;
+        }
+        path.pop();
+        if self
+            .walk_path(path)
+            .is_some_and(|(_, slide)| slide.is_some())
+        {
+            path.pop();
+        }
+    }
+
+    /// Dijkstra over the path steps, or A* if there is a target.
+    fn search(&self, start: vec2<ICoord>, speed: usize, target: Option<vec2<ICoord>>) -> Reachable {
+        // Slides cover two tiles for the cost of one step
+        let heuristic = |pos: vec2<ICoord>| {
+            target.map_or(0, |target| {
+                (shared::distance(pos, target) as usize).div_ceil(2)
+            })
+        };
+
+        let mut reachable = Reachable {
+            start,
+            costs: HashMap::from([(start, 0)]),
+            previous: HashMap::new(),
+        };
+        let mut queue = BinaryHeap::new();
+        queue.push(Reverse((heuristic(start), 0, start.x, start.y)));
+
+        while let Some(Reverse((_, cost, x, y))) = queue.pop() {
+            let pos = vec2(x, y);
+            if Some(pos) == target {
+                break;
+            }
+            if reachable.cost(pos).is_some_and(|best| best < cost) {
+                continue; // Outdated entry
+            }
+
+            for (next, step_cost) in self.path_steps(pos) {
+                let next_cost = cost + step_cost;
+                if next_cost > speed || reachable.cost(next).is_some_and(|best| best <= next_cost) {
+                    continue;
+                }
+                // Paths cannot cross a tile twice, slides pass over the tile in between
+                let delta = next - pos;
+                let slid_over = (delta.x.abs() + delta.y.abs() > 1).then(|| pos + delta / 2);
+                if reachable.passes_over(pos, next)
+                    || slid_over.is_some_and(|tile| reachable.passes_over(pos, tile))
+                {
+                    continue;
+                }
+                reachable.costs.insert(next, next_cost);
+                reachable.previous.insert(next, pos);
+                queue.push(Reverse((
+                    next_cost + heuristic(next),
+                    next_cost,
+                    next.x,
+                    next.y,
+                )));
+            }
+        }
+
+        reachable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use geng::prelude::itertools::Itertools;
+
+    /// Builds a model from the map rows, positions are given as (column, row).
+    fn model(rows: &[&str], rules: Rules) -> SharedModel {
+        let map = Map::parse(&rows.join("\n")).unwrap();
+        SharedModel::new(map, rules)
+    }
+
+    fn tile(model: &SharedModel, col: ICoord, row: ICoord) -> vec2<ICoord> {
+        let bounds = model.map.bounds;
+        vec2(bounds.min.x + col, bounds.max.y - row)
+    }
+
+    /// Gives player 1 the tiles marked with `x`, positions match the map rows.
+    fn claim(model: &mut SharedModel, rows: &[&str]) {
+        for (row, line) in rows.iter().enumerate() {
+            for (col, symbol) in line.chars().enumerate() {
+                if symbol == 'x' {
+                    let pos = tile(model, col as ICoord, row as ICoord);
+                    model.territory.set(pos, Some(1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn closed_loops_enclose_tiles() {
+        let mut model = model(&["....."; 5], Rules::default());
+        claim(&mut model, &[".....", ".xxx.", ".x.x.", ".xxx.", "....."]);
+        assert_eq!(model.enclosed_tiles(1), vec![tile(&model, 2, 2)]);
+    }
+
+    #[test]
+    fn loops_can_be_closed_by_walls() {
+        let mut model = model(&[".....", "..#..", ".....", "....."], Rules::default());
+        claim(&mut model, &[".....", ".x.x.", ".x.x.", ".xxx."]);
+        assert_eq!(model.enclosed_tiles(1), vec![tile(&model, 2, 2)]);
+    }
+
+    #[test]
+    fn open_regions_are_not_enclosed() {
+        let mut open = model(&["....."; 3], Rules::default());
+        claim(&mut open, &[".x.x.", ".x.x.", ".xxx."]);
+        assert!(open.enclosed_tiles(1).is_empty());
+
+        // Pockets closed off by walls alone are not claimed
+        let walled = model(&[".###.", ".#.#.", ".###."], Rules::default());
+        assert!(walled.enclosed_tiles(1).is_empty());
+    }
+
+    #[test]
+    fn parses_map_tiles() {
+        let model = model(&["#B.", ",_~", "*"], Rules::default());
+        let map = &model.map;
+        assert_eq!(map.bounds.max - map.bounds.min, vec2(2, 2));
+        assert_eq!(map.walls, vec![tile(&model, 0, 0)]);
+        assert_eq!(map.bases, vec![tile(&model, 1, 0)]);
+        assert_eq!(map.terrain(tile(&model, 2, 0)), Terrain::Floor);
+        assert_eq!(map.terrain(tile(&model, 0, 1)), Terrain::Mud);
+        assert_eq!(map.terrain(tile(&model, 1, 1)), Terrain::Ice);
+        assert_eq!(map.terrain(tile(&model, 2, 1)), Terrain::Water);
+        assert_eq!(map.terrain(tile(&model, 0, 2)), Terrain::Bush);
+        // Short rows are padded with floor
+        assert_eq!(map.terrain(tile(&model, 2, 2)), Terrain::Floor);
+    }
+
+    #[test]
+    fn rejects_invalid_maps() {
+        assert!(Map::parse("").is_err());
+        assert!(Map::parse("\n\n").is_err());
+        assert!(Map::parse("..\n.x").is_err());
+    }
+
+    #[test]
+    fn walking_through_mud_costs_extra() {
+        let model = model(&[".,."], Rules::default());
+        let path = [0, 1, 2].map(|col| tile(&model, col, 0));
+        assert_eq!(model.walk_path(&path[..2]), Some((2, None)));
+        assert_eq!(model.walk_path(&path), Some((3, None)));
+    }
+
+    #[test]
+    fn walking_onto_ice_forces_a_free_slide() {
+        let model = model(&["._..", "...."], Rules::default());
+        let path = [0, 1, 2, 3].map(|col| tile(&model, col, 0));
+        assert_eq!(
+            model.walk_path(&path[..2]),
+            Some((1, Some(tile(&model, 2, 0))))
+        );
+        assert_eq!(model.walk_path(&path[..3]), Some((1, None)));
+        assert_eq!(model.walk_path(&path), Some((2, None)));
+
+        // Turning off the ice instead of sliding is not allowed
+        let turn = [tile(&model, 0, 0), tile(&model, 1, 0), tile(&model, 1, 1)];
+        assert_eq!(model.walk_path(&turn), None);
+    }
+
+    #[test]
+    fn ice_slides_for_free() {
+        let model = model(&["._.."], Rules::default());
+        let reachable = model.reachable_tiles(tile(&model, 0, 0), 1);
+        assert!(!reachable.contains(tile(&model, 1, 0)));
+        assert_eq!(reachable.cost(tile(&model, 2, 0)), Some(1));
+        assert_eq!(
+            reachable.path_to(tile(&model, 2, 0)),
+            Some(vec![
+                tile(&model, 0, 0),
+                tile(&model, 1, 0),
+                tile(&model, 2, 0)
+            ])
+        );
+    }
+
+    #[test]
+    fn ice_stops_before_walls() {
+        let model = model(&["._#"], Rules::default());
+        let reachable = model.reachable_tiles(tile(&model, 0, 0), 5);
+        assert_eq!(reachable.cost(tile(&model, 1, 0)), Some(1));
+    }
+
+    #[test]
+    fn paths_never_cross_ice_twice() {
+        // The bottom tile is only reachable by sliding over the ice a second time
+        let model = model(&["#..", "._.", "#.#"], Rules::default());
+        let start = tile(&model, 0, 1);
+        let bottom = tile(&model, 1, 2);
+        assert!(!model.reachable_tiles(start, 10).contains(bottom));
+        assert_eq!(model.find_path(start, bottom, 10), None);
+
+        let reachable = model.reachable_tiles(start, 10);
+        for &pos in reachable.costs.keys() {
+            let path = reachable.path_to(pos).unwrap();
+            assert!(path.iter().all_unique(), "{path:?} crosses a tile twice");
+            assert!(model.walk_path(&path).is_some());
+        }
+    }
+
+    #[test]
+    fn blocking_trails_stop_paths() {
+        let mut model = model(
+            &["..."],
+            Rules {
+                trail_blocks: true,
+                ..Rules::default()
+            },
+        );
+        model.trails.push(PlayerTrail {
+            player: 1,
+            pos: tile(&model, 1, 0),
+            connection_from: None,
+            connection_to: tile(&model, 2, 0),
+            turns_left: 1,
+        });
+        let start = tile(&model, 0, 0);
+        assert!(!model.reachable_tiles(start, 5).contains(tile(&model, 2, 0)));
+
+        model.rules.trail_blocks = false;
+        assert!(model.reachable_tiles(start, 5).contains(tile(&model, 2, 0)));
+    }
+
+    #[test]
+    fn trails_in_their_last_turn_still_block() {
+        let mut model = model(
+            &["..."],
+            Rules {
+                trail_blocks: true,
+                ..Rules::default()
+            },
+        );
+        let pos = tile(&model, 1, 0);
+        model.trails.push(PlayerTrail {
+            player: 1,
+            pos,
+            connection_from: None,
+            connection_to: tile(&model, 2, 0),
+            turns_left: 0,
+        });
+        assert!(model.is_blocked_by_trail(pos));
+        assert!(!model.can_build_wall(pos));
+        assert!(
+            !model
+                .reachable_tiles(tile(&model, 0, 0), 5)
+                .contains(tile(&model, 2, 0))
+        );
+    }
+
+    #[test]
+    fn speed_budget_limits_reach() {
+        let model = model(&[".,.."], Rules::default());
+        let start = tile(&model, 0, 0);
+        let reachable = model.reachable_tiles(start, 2);
+        assert_eq!(reachable.cost(tile(&model, 1, 0)), Some(2));
+        assert!(!reachable.contains(tile(&model, 2, 0)));
+
+        let path = model.find_path(start, tile(&model, 3, 0), 4).unwrap();
+        assert_eq!(path.len(), 4);
+        assert_eq!(model.find_path(start, tile(&model, 3, 0), 3), None);
+    }
+}
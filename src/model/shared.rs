@@ -1,6 +1,7 @@
 use super::*;
 
 use geng::prelude::itertools::Itertools;
+use std::collections::VecDeque;
 
 pub const TIME_PER_PLAN: f32 = 5.0;
 pub const TIME_PER_MOVE: f32 = 0.5;
@@ -8,23 +9,107 @@ pub const SPRINT_COOLDOWN: Turns = 3;
 pub const TELEPORT_COOLDOWN: Turns = 3;
 pub const TELEPORT_SPEED: usize = 5;
 pub const THROW_SPEED: usize = 5;
+pub const WALL_COOLDOWN: Turns = 4;
+pub const WALL_SPEED: usize = 5;
+/// How many turns a built wall stands for.
+pub const WALL_DURATION: Turns = 3;
 pub const SCORE_PER_MUSHROOM: Score = 3;
 pub const STARTING_SCREEN_TIME: f32 = 10.0;
 pub const RESULTS_SCREEN_TIME: f32 = 10.0;
+/// Owned tiles needed for one point at the end of each turn.
+pub const TERRITORY_TILES_PER_POINT: usize = 5;
+pub const BOULDER_SPEED: usize = 4;
+pub const BEETLE_SPEED: usize = 2;
+pub const BEETLE_COUNT: usize = 1;
+/// Chance of an environmental event happening in the next turn.
+pub const ENVIRONMENT_EVENT_CHANCE: f64 = 0.2;
+pub const MUSHROOM_RAIN_AMOUNT: usize = 4;
+/// How far players see with fog of war.
+pub const VISION_RADIUS: ICoord = 4;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum GameEvent {
     StartGame,
     StartResolution,
     FinishResolution,
     ResultsOver,
     NextMove,
-    MushroomPickup(vec2<ICoord>),
-    MushroomsCollected(vec2<ICoord>, usize),
-    PlayerStunned(ClientId, vec2<ICoord>),
-    Score(Score, vec2<ICoord>),
-    Teleport,
-    MushroomThrow,
+    MushroomPickup(ClientId, vec2<ICoord>),
+    MushroomsCollected(ClientId, vec2<ICoord>, usize),
+    PlayerStunned(ClientId, vec2<ICoord>, StunCause),
+    /// A player walked, slid or teleported from one tile onto another.
+    PlayerMoved(ClientId, vec2<ICoord>, vec2<ICoord>),
+    /// A player got pushed away by a mushroom hit.
+    PlayerPushed(ClientId, vec2<ICoord>, vec2<ICoord>),
+    /// A player bumped into the tile and got stunned.
+    PlayerBounced(ClientId, vec2<ICoord>),
+    /// A flying mushroom moved from one tile onto another.
+    MushroomMoved(vec2<ICoord>, vec2<ICoord>),
+    Score(ClientId, Score, vec2<ICoord>),
+    /// A player teleported onto the tile.
+    Teleport(ClientId, vec2<ICoord>),
+    /// A player threw a mushroom from the tile.
+    MushroomThrow(ClientId, vec2<ICoord>),
+    WallBuilt(vec2<ICoord>),
+    HazardSpawned(vec2<ICoord>),
+    BoulderCrumbled(vec2<ICoord>),
+    MushroomRain(Vec<vec2<ICoord>>),
+}
+
+/// What a player got stunned by.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum StunCause {
+    /// Hit by a flying mushroom.
+    Mushroom,
+    /// Ran into a hazard or got run over by one.
+    Hazard,
+    /// Stepped onto a trail.
+    Trail,
+    /// Bumped into another player or an obstacle.
+    Collision,
+}
+
+impl GameEvent {
+    /// Tiles the event happens on.
+    pub fn positions(&self) -> Vec<vec2<ICoord>> {
+        match self {
+            GameEvent::StartGame
+            | GameEvent::StartResolution
+            | GameEvent::FinishResolution
+            | GameEvent::ResultsOver
+            | GameEvent::NextMove => Vec::new(),
+            GameEvent::MushroomPickup(_, pos)
+            | GameEvent::MushroomsCollected(_, pos, _)
+            | GameEvent::PlayerStunned(_, pos, _)
+            | GameEvent::Score(_, _, pos)
+            | GameEvent::Teleport(_, pos)
+            | GameEvent::MushroomThrow(_, pos)
+            | GameEvent::PlayerBounced(_, pos)
+            | GameEvent::WallBuilt(pos)
+            | GameEvent::HazardSpawned(pos)
+            | GameEvent::BoulderCrumbled(pos) => vec![*pos],
+            GameEvent::PlayerMoved(_, from, to)
+            | GameEvent::PlayerPushed(_, from, to)
+            | GameEvent::MushroomMoved(from, to) => vec![*from, *to],
+            GameEvent::MushroomRain(positions) => positions.clone(),
+        }
+    }
+
+    /// The player the event happens to.
+    pub fn player(&self) -> Option<ClientId> {
+        match *self {
+            GameEvent::MushroomPickup(player_id, _)
+            | GameEvent::MushroomsCollected(player_id, ..)
+            | GameEvent::PlayerStunned(player_id, ..)
+            | GameEvent::Score(player_id, ..)
+            | GameEvent::Teleport(player_id, _)
+            | GameEvent::MushroomThrow(player_id, _)
+            | GameEvent::PlayerMoved(player_id, ..)
+            | GameEvent::PlayerPushed(player_id, ..)
+            | GameEvent::PlayerBounced(player_id, _) => Some(player_id),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,10 +125,57 @@ pub struct Mushroom {
     pub position: vec2<ICoord>,
     pub direction: vec2<ICoord>,
     pub speed_left: usize,
+    /// The player who threw the mushroom.
+    pub thrown_by: Option<ClientId>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum HazardKind {
+    /// Rolls in a straight line until it crumbles against an obstacle.
+    Boulder,
+    /// Patrols back and forth, turning around at obstacles.
+    Beetle,
+}
+
+impl HazardKind {
+    pub fn speed(self) -> usize {
+        match self {
+            HazardKind::Boulder => BOULDER_SPEED,
+            HazardKind::Beetle => BEETLE_SPEED,
+        }
+    }
+}
+
+/// A non-player entity moving on its own during resolution.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hazard {
+    pub kind: HazardKind,
+    pub position: vec2<ICoord>,
+    pub direction: vec2<ICoord>,
+    pub speed_left: usize,
+}
+
+/// Announced during planning and happens at the start of the next resolution.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum EnvironmentEvent {
+    /// Mushrooms fall onto the tiles.
+    MushroomRain(Vec<vec2<ICoord>>),
+    /// A boulder rolls in from the edge of the map.
+    Boulder {
+        position: vec2<ICoord>,
+        direction: vec2<ICoord>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemporaryWall {
+    pub position: vec2<ICoord>,
+    pub turns_left: Turns,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SharedModel {
+    pub rules: Rules,
     pub map: Map,
     pub turns_max: Turns,
 
@@ -52,27 +184,46 @@ pub struct SharedModel {
 
     pub bases: Vec<vec2<ICoord>>,
     pub players: HashMap<ClientId, Player>,
+    /// Players out of the receiving client's sight with fog of war or hiding in a bush,
+    /// only their customization and score are kept.
+    pub hidden_players: HashMap<ClientId, Player>,
     pub mushrooms: Vec<Mushroom>,
     pub trails: Vec<PlayerTrail>,
+    pub temporary_walls: Vec<TemporaryWall>,
+    /// Owner of each tile with the territory rules.
+    pub territory: TileGrid<Option<ClientId>>,
+    pub hazards: Vec<Hazard>,
+    pub next_event: Option<EnvironmentEvent>,
 }
 
 impl SharedModel {
-    pub fn new(map: Map) -> Self {
+    pub fn new(map: Map, rules: Rules) -> Self {
         let mut model = Self {
             turn_current: 1,
             phase: Phase::Planning {
                 time_left: FTime::new(TIME_PER_PLAN),
             },
 
-            bases: vec![map.bounds.center()],
+            bases: if map.bases.is_empty() {
+                vec![map.bounds.center()]
+            } else {
+                map.bases.clone()
+            },
             players: HashMap::new(),
+            hidden_players: HashMap::new(),
             mushrooms: Vec::new(),
             trails: Vec::new(),
+            temporary_walls: Vec::new(),
+            territory: TileGrid::new(map.bounds, None),
+            hazards: Vec::new(),
+            next_event: None,
 
             turns_max: 30,
             map,
+            rules,
         };
         model.spawn_mushroom();
+        model.spawn_hazards();
         model
     }
 
@@ -120,11 +271,145 @@ impl SharedModel {
         events
     }
 
+    /// All players, including the ones hidden by fog of war.
+    pub fn all_players(&self) -> impl Iterator<Item = &Player> {
+        self.players.values().chain(self.hidden_players.values())
+    }
+
+    /// Tiles within the vision radius that are not behind walls.
+    pub fn visible_tiles(&self, from: vec2<ICoord>) -> TileGrid<bool> {
+        let mut visible = TileGrid::new(self.map.bounds, false);
+        for dx in -VISION_RADIUS..=VISION_RADIUS {
+            for dy in -VISION_RADIUS..=VISION_RADIUS {
+                let pos = from + vec2(dx, dy);
+                if dx * dx + dy * dy <= VISION_RADIUS * VISION_RADIUS
+                    && self.map.is_in_bounds(pos)
+                    && self.has_line_of_sight(from, pos)
+                {
+                    visible.set(pos, true);
+                }
+            }
+        }
+        visible
+    }
+
+    /// Checks that no walls stand on the line between the tiles.
+    fn has_line_of_sight(&self, from: vec2<ICoord>, to: vec2<ICoord>) -> bool {
+        let delta = to - from;
+        let steps = delta.x.abs().max(delta.y.abs());
+        (1..steps).all(|i| {
+            let t = i as f32 / steps as f32;
+            let pos = (from.as_f32() + delta.as_f32() * t).map(|x| x.round() as ICoord);
+            !self.is_wall(pos)
+        })
+    }
+
+    /// A copy of the model with only the information the client is allowed to see.
+    pub fn visible_to(&self, client_id: ClientId) -> Self {
+        let mut model = self.clone();
+        let Some(viewer) = self.players.get(&client_id) else {
+            // Spectators see everything
+            return model;
+        };
+
+        let visible = self.vision_of(viewer);
+        let is_visible = |pos: vec2<ICoord>| visible.get(pos).copied().unwrap_or(false);
+
+        for player_id in self.hidden_from(viewer, &visible) {
+            if let Some(player) = model.players.remove(&player_id) {
+                let mut hidden =
+                    Player::new(player_id, player.customization, vec2::ZERO, &self.rules);
+                // Points delivered during the resolution would reveal the base visit
+                hidden.score = if let Phase::Resolution { .. } = self.phase {
+                    player.resolution_score
+                } else {
+                    player.score
+                };
+                hidden.match_stats = player.match_stats;
+                model.hidden_players.insert(player_id, hidden);
+            }
+        }
+        if !self.rules.fog_of_war {
+            return model;
+        }
+
+        model
+            .mushrooms
+            .retain(|mushroom| is_visible(mushroom.position));
+        model.trails.retain(|trail| is_visible(trail.pos));
+        model.hazards.retain(|hazard| is_visible(hazard.position));
+        model
+            .temporary_walls
+            .retain(|wall| is_visible(wall.position));
+        for (pos, owner) in self.territory.iter() {
+            if owner.is_some() && !is_visible(pos) {
+                model.territory.set(pos, None);
+            }
+        }
+        model
+    }
+
+    /// Tiles the player sees, all of them without fog of war.
+    fn vision_of(&self, viewer: &Player) -> TileGrid<bool> {
+        if self.rules.fog_of_war {
+            self.visible_tiles(viewer.pos)
+        } else {
+            TileGrid::new(self.map.bounds, true)
+        }
+    }
+
+    /// Players the viewer cannot see, either out of sight or hiding in a bush.
+    fn hidden_from(&self, viewer: &Player, visible: &TileGrid<bool>) -> Vec<ClientId> {
+        let is_visible = |pos: vec2<ICoord>| visible.get(pos).copied().unwrap_or(false);
+        self.players
+            .values()
+            .filter(|player| {
+                player.id != viewer.id
+                    && (!is_visible(player.pos)
+                        || self.map.terrain(player.pos) == Terrain::Bush
+                            && distance(player.pos, viewer.pos) > 1)
+            })
+            .map(|player| player.id)
+            .collect()
+    }
+
+    /// The events the client is allowed to see, only the ones fully in sight
+    /// and not involving players hidden in bushes are kept.
+    pub fn events_visible_to(&self, client_id: ClientId, events: &[GameEvent]) -> Vec<GameEvent> {
+        let Some(viewer) = self.players.get(&client_id) else {
+            return events.to_vec();
+        };
+
+        let visible = self.vision_of(viewer);
+        let is_visible = |pos: vec2<ICoord>| visible.get(pos).copied().unwrap_or(false);
+        let hidden = self.hidden_from(viewer, &visible);
+        events
+            .iter()
+            .filter_map(|event| match event {
+                GameEvent::MushroomRain(positions) => {
+                    let positions: Vec<_> = positions
+                        .iter()
+                        .copied()
+                        .filter(|&pos| is_visible(pos))
+                        .collect();
+                    // Rain falling only in the fog is not heard either
+                    (!positions.is_empty()).then_some(GameEvent::MushroomRain(positions))
+                }
+                _ => (event.positions().into_iter().all(is_visible)
+                    && event
+                        .player()
+                        .is_none_or(|player_id| !hidden.contains(&player_id)))
+                .then(|| event.clone()),
+            })
+            .collect()
+    }
+
     pub fn spawn_mushroom(&mut self) {
         let mut position = None;
         for _ in 0..10 {
             let pos = self.map.random_position();
-            if self.map.walls.contains(&pos)
+            if !self.is_walkable(pos)
+                || self.is_blocked_by_trail(pos)
                 || self.bases.iter().any(|base| distance(*base, pos) <= 2)
                 || self
                     .players
@@ -142,9 +427,128 @@ impl SharedModel {
             position,
             direction: vec2::ZERO,
             speed_left: 0,
+            thrown_by: None,
         });
     }
 
+    /// Places the patrolling hazards at the start of the game.
+    pub fn spawn_hazards(&mut self) {
+        if !self.rules.hazards {
+            return;
+        }
+
+        let mut rng = thread_rng();
+        for _ in 0..BEETLE_COUNT {
+            let Some(position) = (0..10)
+                .map(|_| self.map.random_position())
+                .find(|&pos| self.is_free_for_hazard(pos))
+            else {
+                continue;
+            };
+            let direction = *[vec2(1, 0), vec2(-1, 0), vec2(0, 1), vec2(0, -1)]
+                .choose(&mut rng)
+                .unwrap();
+            self.hazards.push(Hazard {
+                kind: HazardKind::Beetle,
+                position,
+                direction,
+                speed_left: 0,
+            });
+        }
+    }
+
+    /// Randomly schedules an environmental event for the next turn.
+    pub fn roll_environment_event(&mut self) {
+        let mut rng = thread_rng();
+        if !self.rules.hazards || !rng.gen_bool(ENVIRONMENT_EVENT_CHANCE) {
+            return;
+        }
+
+        let bounds = self.map.bounds;
+        self.next_event = if rng.gen_bool(0.5) {
+            let positions: Vec<vec2<ICoord>> = (0..MUSHROOM_RAIN_AMOUNT * 3)
+                .map(|_| self.map.random_position())
+                .filter(|&pos| self.is_walkable(pos) && !self.bases.contains(&pos))
+                .unique()
+                .take(MUSHROOM_RAIN_AMOUNT)
+                .collect();
+            (!positions.is_empty()).then_some(EnvironmentEvent::MushroomRain(positions))
+        } else {
+            // Roll in from a random edge
+            let direction = *[vec2(1, 0), vec2(-1, 0), vec2(0, 1), vec2(0, -1)]
+                .choose(&mut rng)
+                .unwrap();
+            let position = if direction.x != 0 {
+                let x = if direction.x > 0 {
+                    bounds.min.x
+                } else {
+                    bounds.max.x
+                };
+                vec2(x, rng.gen_range(bounds.min.y..=bounds.max.y))
+            } else {
+                let y = if direction.y > 0 {
+                    bounds.min.y
+                } else {
+                    bounds.max.y
+                };
+                vec2(rng.gen_range(bounds.min.x..=bounds.max.x), y)
+            };
+            self.is_walkable(position)
+                .then_some(EnvironmentEvent::Boulder {
+                    position,
+                    direction,
+                })
+        };
+    }
+
+    fn trigger_event(&mut self, event: EnvironmentEvent) -> Vec<GameEvent> {
+        let mut events = Vec::new();
+        match event {
+            EnvironmentEvent::MushroomRain(positions) => {
+                let positions: Vec<vec2<ICoord>> = positions
+                    .into_iter()
+                    .filter(|&pos| {
+                        !self
+                            .mushrooms
+                            .iter()
+                            .any(|mushroom| mushroom.position == pos)
+                            && !self.players.values().any(|player| player.pos == pos)
+                    })
+                    .collect();
+                self.mushrooms
+                    .extend(positions.iter().map(|&position| Mushroom {
+                        position,
+                        direction: vec2::ZERO,
+                        speed_left: 0,
+                        thrown_by: None,
+                    }));
+                events.push(GameEvent::MushroomRain(positions));
+            }
+            EnvironmentEvent::Boulder {
+                position,
+                direction,
+            } => {
+                if self.is_free_for_hazard(position) {
+                    self.hazards.push(Hazard {
+                        kind: HazardKind::Boulder,
+                        position,
+                        direction,
+                        speed_left: BOULDER_SPEED,
+                    });
+                    events.push(GameEvent::HazardSpawned(position));
+                }
+            }
+        }
+        events
+    }
+
+    fn is_free_for_hazard(&self, pos: vec2<ICoord>) -> bool {
+        self.is_walkable(pos)
+            && !self.bases.contains(&pos)
+            && !self.players.values().any(|player| player.pos == pos)
+            && !self.hazards.iter().any(|hazard| hazard.position == pos)
+    }
+
     pub fn start_game(&mut self) {
         let Phase::Starting { .. } = self.phase else {
             return;
@@ -169,11 +573,22 @@ impl SharedModel {
 
             // Update cooldowns
             player.cooldown_sprint -= 1;
+            player.cooldown_wall -= 1;
             if !player.is_channeling {
                 player.cooldown_teleport -= 1;
             }
         }
 
+        // Crumble walls
+        for wall in &mut self.temporary_walls {
+            wall.turns_left -= 1;
+        }
+        self.temporary_walls.retain(|wall| wall.turns_left > 0);
+
+        if self.rules.territory {
+            self.update_territory();
+        }
+
         if self.turn_current >= self.turns_max {
             self.phase = Phase::Results {
                 time_left: FTime::new(RESULTS_SCREEN_TIME),
@@ -189,10 +604,20 @@ impl SharedModel {
     pub fn new_game(&mut self) {
         self.mushrooms.clear();
         self.trails.clear();
+        self.temporary_walls.clear();
+        self.territory.fill(None);
+        self.hazards.clear();
+        self.next_event = None;
         self.turn_current = 1;
         for player in self.players.values_mut() {
-            *player = Player::new(player.id, player.customization.clone(), player.pos);
+            *player = Player::new(
+                player.id,
+                player.customization.clone(),
+                player.pos,
+                &self.rules,
+            );
         }
+        self.spawn_hazards();
         self.phase = Phase::Starting {
             time_left: FTime::new(STARTING_SCREEN_TIME),
         };
@@ -220,7 +645,7 @@ impl SharedModel {
                 let speed = match player.submitted_move {
                     PlayerMove::Normal { sprint, .. } => {
                         if sprint {
-                            player.cooldown_sprint = SPRINT_COOLDOWN;
+                            player.cooldown_sprint = player.sprint_cooldown();
                         }
                         player.speed(sprint)
                     }
@@ -232,13 +657,31 @@ impl SharedModel {
                         player.is_channeling = false;
                         TELEPORT_SPEED
                     }
-                    PlayerMove::Throw { .. } => THROW_SPEED,
+                    PlayerMove::Throw { .. } => player.throw_speed(),
+                    PlayerMove::BuildWall { .. } => {
+                        player.cooldown_wall = WALL_COOLDOWN;
+                        WALL_SPEED
+                    }
                 };
                 player.resolution_speed_max = speed;
                 player.resolution_speed_left = speed;
             }
+            player.resolution_score = player.score;
+            player.resolution_path_index = 0;
+            player.resolution_steps = 0;
+            player.is_wading = false;
         }
-        self.trails.clear();
+
+        for hazard in &mut self.hazards {
+            hazard.speed_left = hazard.kind.speed();
+        }
+
+        // Decay trails from previous turns
+        for trail in &mut self.trails {
+            trail.turns_left -= 1;
+        }
+        self.trails.retain(|trail| trail.turns_left >= 0);
+
         self.phase = Phase::Resolution {
             next_move_in: FTime::ZERO,
         };
@@ -250,11 +693,17 @@ impl SharedModel {
     pub fn resolve_next_move(&mut self) -> (Vec<GameEvent>, bool) {
         let mut events = Vec::new();
 
+        // Environmental events happen before the first move
+        if let Some(event) = self.next_event.take() {
+            events.extend(self.trigger_event(event));
+        }
+
         let resolving_speed = self
             .players
             .values()
             .map(|player| player.resolution_speed_left)
             .chain(self.mushrooms.iter().map(|mushroom| mushroom.speed_left))
+            .chain(self.hazards.iter().map(|hazard| hazard.speed_left))
             .max()
             .unwrap_or(0);
         if resolving_speed == 0 {
@@ -287,17 +736,23 @@ impl SharedModel {
                         mushroom.speed_left = 0;
                         let push_to = player.pos + mushroom.direction;
                         let player_id = player.id;
-                        if self.map.is_in_bounds(push_to)
-                            && !self.map.walls.contains(&push_to)
+                        let thrower = mushroom.thrown_by;
+                        if self.is_walkable(push_to)
                             && !self.players.values().any(|player| player.pos == push_to)
                             && let Some(player) = self.players.get_mut(&player_id)
                         {
+                            events.push(GameEvent::PlayerPushed(player_id, player.pos, push_to));
                             player.pos = push_to;
                         }
-                        events.extend(self.stun_player(player_id, 1));
-                    } else if self.map.walls.contains(&target) || !self.map.is_in_bounds(target) {
+                        events.extend(self.stun_player(player_id, 1, StunCause::Mushroom));
+                        self.credit_hit(thrower, player_id);
+                    } else if self.is_wall(target)
+                        || !self.map.is_in_bounds(target)
+                        || self.hazards.iter().any(|hazard| hazard.position == target)
+                    {
                         mushroom.speed_left = 0;
                     } else {
+                        events.push(GameEvent::MushroomMoved(mushroom.position, target));
                         mushroom.position = target;
                         mushroom.speed_left -= 1;
                     }
@@ -312,6 +767,47 @@ impl SharedModel {
             }
         }
 
+        // Hazard moves
+        let mut hazard_moves_any = false;
+        let mut crumbled = Vec::new();
+        for hazard_i in 0..self.hazards.len() {
+            let hazard = &mut self.hazards[hazard_i];
+            if hazard.speed_left != resolving_speed {
+                continue;
+            }
+            hazard_moves_any = true;
+            hazard.speed_left -= 1;
+            let hazard = hazard.clone();
+            let target = hazard.position + hazard.direction;
+
+            let hit_player = self
+                .players
+                .values()
+                .find(|player| player.pos == target)
+                .map(|player| player.id);
+            if let Some(player_id) = hit_player {
+                events.extend(self.stun_player(player_id, 1, StunCause::Hazard));
+            }
+
+            if hit_player.is_some() || !self.is_walkable(target) {
+                match hazard.kind {
+                    HazardKind::Boulder => {
+                        crumbled.push(hazard_i);
+                        events.push(GameEvent::BoulderCrumbled(hazard.position));
+                    }
+                    HazardKind::Beetle => {
+                        // Turn around
+                        self.hazards[hazard_i].direction = -hazard.direction;
+                    }
+                }
+            } else {
+                self.hazards[hazard_i].position = target;
+            }
+        }
+        for hazard_i in crumbled.into_iter().rev() {
+            self.hazards.remove(hazard_i);
+        }
+
         let mut player_moves: HashMap<vec2<ICoord>, Vec<ClientId>> = HashMap::new();
         for player_id in self.players.keys().cloned().collect::<Vec<_>>() {
             let Some(player) = self.players.get_mut(&player_id) else {
@@ -323,10 +819,14 @@ impl SharedModel {
             match &player.submitted_move {
                 PlayerMove::Normal { path, .. } => {
                     if player.resolution_speed_left == resolving_speed
-                        && let Some(&pos) =
-                            path.get(1 + player.resolution_speed_max - player.resolution_speed_left)
+                        && let Some(&pos) = path.get(player.resolution_path_index + 1)
                     {
-                        player_moves.entry(pos).or_default().push(player.id);
+                        if self.map.terrain(pos).move_cost() > 1 && !player.is_wading {
+                            // Wade into mud, stepping in on the next move
+                            player.is_wading = true;
+                        } else {
+                            player_moves.entry(pos).or_default().push(player.id);
+                        }
                     }
                 }
                 PlayerMove::TeleportChanneling => {
@@ -343,7 +843,8 @@ impl SharedModel {
                             .entry(*teleport_to)
                             .or_default()
                             .push(player.id);
-                        events.push(GameEvent::Teleport);
+                        events.push(GameEvent::Teleport(player.id, *teleport_to));
+                        player.match_stats.teleports += 1;
                     }
                 }
                 &PlayerMove::Throw { direction } => {
@@ -354,32 +855,52 @@ impl SharedModel {
                             position,
                             direction,
                             speed_left: player.resolution_speed_left.saturating_sub(1),
+                            thrown_by: Some(player.id),
                         };
                         player.mushrooms -= 1;
+                        player.match_stats.mushrooms_thrown += 1;
                         player.resolution_speed_left = 0;
-                        events.push(GameEvent::MushroomThrow);
+                        events.push(GameEvent::MushroomThrow(player.id, player.pos));
+                        events.push(GameEvent::MushroomMoved(player.pos, position));
                         if let Some(player) =
                             self.players.values().find(|player| player.pos == position)
                         {
                             mushroom.speed_left = 0;
                             let push_to = player.pos + mushroom.direction;
                             let player_id = player.id;
-                            if self.map.is_in_bounds(push_to)
-                                && !self.map.walls.contains(&push_to)
+                            if self.is_walkable(push_to)
                                 && !self.players.values().any(|player| player.pos == push_to)
                                 && let Some(player) = self.players.get_mut(&player_id)
                             {
+                                events
+                                    .push(GameEvent::PlayerPushed(player_id, player.pos, push_to));
                                 player.pos = push_to;
                             }
-                            events.extend(self.stun_player(player_id, 1));
+                            events.extend(self.stun_player(player_id, 1, StunCause::Mushroom));
+                            self.credit_hit(mushroom.thrown_by, player_id);
                         }
                         self.mushrooms.push(mushroom);
                     }
                 }
+                &PlayerMove::BuildWall { direction } => {
+                    if player.resolution_speed_left == resolving_speed {
+                        // Build on the first move
+                        player.resolution_speed_left = 0;
+                        let position = player.pos + direction;
+                        let turns_left = player.wall_duration();
+                        if self.can_build_wall(position) {
+                            self.temporary_walls.push(TemporaryWall {
+                                position,
+                                turns_left,
+                            });
+                            events.push(GameEvent::WallBuilt(position));
+                        }
+                    }
+                }
             }
         }
 
-        if mushroom_moves_any || !player_moves.is_empty() {
+        if mushroom_moves_any || hazard_moves_any || !player_moves.is_empty() {
             events.push(GameEvent::NextMove);
         }
 
@@ -389,64 +910,39 @@ impl SharedModel {
                 // Just move the player - check for other collisions
                 for player_id in players {
                     // Check collisions
-                    if self.players.values().any(|player| player.pos == target)
-                        || self.trails.iter().any(|trail| trail.pos == target)
-                    {
-                        events.extend(self.stun_player(player_id, 1));
+                    if let Some(cause) = self.obstacle_at(target) {
+                        events.push(GameEvent::PlayerBounced(player_id, target));
+                        events.extend(self.stun_player(player_id, 1, cause));
                         continue;
                     }
 
                     // Move
+                    events.extend(self.move_player(player_id, target));
                     if let Some(player) = self.players.get_mut(&player_id) {
-                        if let Some((shroom_i, shroom)) = self
-                            .mushrooms
-                            .iter()
-                            .enumerate()
-                            .find(|(_, shroom)| shroom.position == target)
-                        {
-                            // Collect mushroom
-                            player.mushrooms += 1;
-                            events.push(GameEvent::MushroomPickup(target));
-                            if shroom.speed_left > 0 {
-                                // Get hit
-                                events.extend(self.stun_player(player_id, 1));
-                            }
-                            self.mushrooms.swap_remove(shroom_i);
-                        }
-
-                        let player = self.players.get_mut(&player_id).unwrap();
-
-                        if self.bases.contains(&target) {
-                            // Submit resources to base
-                            let score = SCORE_PER_MUSHROOM * player.mushrooms as Score;
-                            player.score += score;
-                            events.push(GameEvent::MushroomsCollected(target, player.mushrooms));
-                            events.push(GameEvent::Score(score, player.pos));
-                            player.mushrooms = 0;
-                        }
-
-                        if let PlayerMove::Normal { path, .. } = &player.submitted_move {
-                            let connection_from = path
-                                .iter()
-                                .position(|&pos| pos == player.pos)
-                                .and_then(|i| i.checked_sub(1))
-                                .and_then(|i| path.get(i).copied());
-                            self.trails.push(PlayerTrail {
-                                player: player.id,
-                                pos: player.pos,
-                                connection_from,
-                                connection_to: target,
-                            });
-                        }
-                        player.pos = target;
                         player.resolution_speed_left =
                             player.resolution_speed_left.saturating_sub(1);
                     }
+
+                    // Slide on ice for free
+                    if let Some(slide) = self.next_slide(player_id) {
+                        let obstacle = if self.is_walkable(slide) {
+                            self.obstacle_at(slide)
+                        } else {
+                            Some(StunCause::Collision)
+                        };
+                        if let Some(cause) = obstacle {
+                            events.push(GameEvent::PlayerBounced(player_id, slide));
+                            events.extend(self.stun_player(player_id, 1, cause));
+                        } else {
+                            events.extend(self.move_player(player_id, slide));
+                        }
+                    }
                 }
             } else {
                 // Bounce all players
                 for player in players {
-                    events.extend(self.stun_player(player, 1));
+                    events.push(GameEvent::PlayerBounced(player, target));
+                    events.extend(self.stun_player(player, 1, StunCause::Collision));
                 }
             }
         }
@@ -456,11 +952,229 @@ impl SharedModel {
             player.resolution_speed_left = player.resolution_speed_left.min(resolving_speed - 1);
         }
 
+        // Sink mushrooms that stopped in water
+        let map = &self.map;
+        self.mushrooms.retain(|mushroom| {
+            mushroom.speed_left > 0 || map.terrain(mushroom.position).is_walkable()
+        });
+
         (events, true)
     }
 
+    /// Moves the player onto the tile, collecting mushrooms and leaving a trail behind.
+    fn move_player(&mut self, player_id: ClientId, target: vec2<ICoord>) -> Vec<GameEvent> {
+        let mut events = Vec::new();
+        let sliding = self.next_slide(player_id) == Some(target);
+
+        let Some(player) = self.players.get_mut(&player_id) else {
+            return events;
+        };
+        if player.can_carry_more()
+            && let Some((shroom_i, shroom)) = self
+                .mushrooms
+                .iter()
+                .enumerate()
+                .find(|(_, shroom)| shroom.position == target)
+        {
+            // Collect mushroom
+            player.mushrooms += 1;
+            player.match_stats.mushrooms_picked += 1;
+            events.push(GameEvent::MushroomPickup(player_id, target));
+            if shroom.speed_left > 0 {
+                // Get hit
+                let thrower = shroom.thrown_by;
+                events.extend(self.stun_player(player_id, 1, StunCause::Mushroom));
+                self.credit_hit(thrower, player_id);
+            }
+            self.mushrooms.swap_remove(shroom_i);
+        }
+
+        let player = self.players.get_mut(&player_id).unwrap();
+
+        if self.bases.contains(&target) {
+            // Submit resources to base
+            let score = player.score_per_mushroom() * player.mushrooms as Score;
+            player.score += score;
+            player.match_stats.mushrooms_delivered += player.mushrooms;
+            events.push(GameEvent::MushroomsCollected(
+                player_id,
+                target,
+                player.mushrooms,
+            ));
+            events.push(GameEvent::Score(player_id, score, player.pos));
+            player.mushrooms = 0;
+        }
+
+        if let PlayerMove::Normal { path, .. } = &player.submitted_move {
+            let connection_from = path
+                .iter()
+                .position(|&pos| pos == player.pos)
+                .and_then(|i| i.checked_sub(1))
+                .and_then(|i| path.get(i).copied());
+            self.trails.push(PlayerTrail {
+                player: player.id,
+                pos: player.pos,
+                connection_from,
+                connection_to: target,
+                turns_left: self.rules.trail_duration,
+            });
+        }
+        events.push(GameEvent::PlayerMoved(player_id, player.pos, target));
+        if let PlayerMove::Normal { sprint, .. } = player.submitted_move
+            && !sliding
+        {
+            player.resolution_steps += 1;
+            let stats = &mut player.match_stats;
+            stats.tiles_walked += 1;
+            if sprint {
+                stats.longest_sprint = stats.longest_sprint.max(player.resolution_steps);
+            }
+        }
+        player.pos = target;
+        player.resolution_path_index += 1;
+        player.is_wading = false;
+        if self.rules.territory {
+            self.territory.set(target, Some(player_id));
+        }
+
+        events
+    }
+
+    /// The tile the player slides onto after stepping on ice along the path.
+    fn next_slide(&self, player_id: ClientId) -> Option<vec2<ICoord>> {
+        let player = self.players.get(&player_id)?;
+        let PlayerMove::Normal { path, .. } = &player.submitted_move else {
+            return None;
+        };
+        let index = player.resolution_path_index;
+        let from = *path.get(index.checked_sub(1)?)?;
+        let to = *path.get(index)?;
+        let next = *path.get(index + 1)?;
+        (to == player.pos && self.ice_slide(from, to) == Some(next)).then_some(next)
+    }
+
+    /// Claims regions enclosed by each player's tiles
+    /// and awards points for the owned area.
+    fn update_territory(&mut self) {
+        let mut player_ids: Vec<ClientId> = self.players.keys().copied().collect();
+        player_ids.sort();
+        for &player_id in &player_ids {
+            for pos in self.enclosed_tiles(player_id) {
+                self.territory.set(pos, Some(player_id));
+            }
+        }
+
+        for player in self.players.values_mut() {
+            let owned = self
+                .territory
+                .iter()
+                .filter(|(_, owner)| **owner == Some(player.id))
+                .count();
+            player.score += (owned / TERRITORY_TILES_PER_POINT) as Score;
+        }
+    }
+
+    /// Finds the walkable tiles that cannot reach the edge of the map without crossing
+    /// the player's territory, walls and water close off regions as well.
+    pub fn enclosed_tiles(&self, player_id: ClientId) -> Vec<vec2<ICoord>> {
+        let bounds = self.map.bounds;
+        let directions = [vec2(1, 0), vec2(-1, 0), vec2(0, 1), vec2(0, -1)];
+        let is_owned = |pos: vec2<ICoord>| self.territory.get(pos) == Some(&Some(player_id));
+        let is_open = |pos: vec2<ICoord>| {
+            self.is_walkable(pos)
+                && self
+                    .territory
+                    .get(pos)
+                    .is_some_and(|owner| *owner != Some(player_id))
+        };
+
+        // Flood fill from the edges
+        let mut outside = TileGrid::new(bounds, false);
+        let mut queue: VecDeque<vec2<ICoord>> = self
+            .territory
+            .iter()
+            .map(|(pos, _)| pos)
+            .filter(|pos| {
+                pos.x == bounds.min.x
+                    || pos.x == bounds.max.x
+                    || pos.y == bounds.min.y
+                    || pos.y == bounds.max.y
+            })
+            .filter(|&pos| is_open(pos))
+            .collect();
+        for &pos in &queue {
+            outside.set(pos, true);
+        }
+        while let Some(pos) = queue.pop_front() {
+            for dir in directions {
+                let next = pos + dir;
+                if is_open(next) && outside.get(next) == Some(&false) {
+                    outside.set(next, true);
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        // Only regions bordering the player's territory are enclosed,
+        // pockets closed off by walls alone belong to nobody
+        let mut enclosed = TileGrid::new(bounds, false);
+        let mut queue: VecDeque<vec2<ICoord>> = outside
+            .iter()
+            .filter(|&(pos, outside)| {
+                !*outside && is_open(pos) && directions.iter().any(|&dir| is_owned(pos + dir))
+            })
+            .map(|(pos, _)| pos)
+            .collect();
+        for &pos in &queue {
+            enclosed.set(pos, true);
+        }
+        while let Some(pos) = queue.pop_front() {
+            for dir in directions {
+                let next = pos + dir;
+                if is_open(next) && enclosed.get(next) == Some(&false) {
+                    enclosed.set(next, true);
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        enclosed
+            .iter()
+            .filter(|(_, enclosed)| **enclosed)
+            .map(|(pos, _)| pos)
+            .collect()
+    }
+
+    /// Counts a mushroom hit for the player who threw it.
+    fn credit_hit(&mut self, thrower: Option<ClientId>, target: ClientId) {
+        if let Some(player) = thrower
+            .filter(|&player_id| player_id != target)
+            .and_then(|player_id| self.players.get_mut(&player_id))
+        {
+            player.match_stats.hits_landed += 1;
+        }
+    }
+
+    /// What stuns a player stepping onto the tile, if anything.
+    fn obstacle_at(&self, pos: vec2<ICoord>) -> Option<StunCause> {
+        if self.hazards.iter().any(|hazard| hazard.position == pos) {
+            Some(StunCause::Hazard)
+        } else if self.trails.iter().any(|trail| trail.pos == pos) {
+            Some(StunCause::Trail)
+        } else if self.players.values().any(|player| player.pos == pos) {
+            Some(StunCause::Collision)
+        } else {
+            None
+        }
+    }
+
     #[must_use]
-    pub fn stun_player(&mut self, player_id: ClientId, duration: Turns) -> Vec<GameEvent> {
+    pub fn stun_player(
+        &mut self,
+        player_id: ClientId,
+        duration: Turns,
+        cause: StunCause,
+    ) -> Vec<GameEvent> {
         let mut events = Vec::new();
 
         let Some(player) = self.players.get_mut(&player_id) else {
@@ -469,10 +1183,17 @@ impl SharedModel {
 
         player.resolution_speed_left = 0;
         player.is_channeling = false;
+
+        let duration = duration - player.stats.stun_resistance;
+        if duration <= 0 {
+            // Fully resisted, only the current move is stopped
+            return events;
+        }
         player.stunned_duration = Some(duration);
 
         // Drop mushroom
         if player.mushrooms > 0
+            && player.passive != Some(Passive::Slippery)
             && let PlayerMove::Normal { path, .. } = &player.submitted_move
             && let Some(&start_pos) = path.first()
             && start_pos != player.pos
@@ -482,12 +1203,14 @@ impl SharedModel {
                 position: start_pos,
                 direction: vec2::ZERO,
                 speed_left: 0,
+                thrown_by: None,
             });
         }
 
         std::mem::take(&mut player.submitted_move);
+        player.match_stats.times_stunned += 1;
 
-        events.push(GameEvent::PlayerStunned(player_id, player.pos));
+        events.push(GameEvent::PlayerStunned(player_id, player.pos, cause));
         events
     }
 
@@ -506,21 +1229,92 @@ impl SharedModel {
             return false;
         }
 
+        if let Some(ability) = Ability::required_by(player_move)
+            && !player.customization.loadout.contains(ability)
+        {
+            return false; // Ability is not in the loadout
+        }
+
         match player_move {
             PlayerMove::Normal { path, sprint } => self.validate_path(player_id, path, *sprint),
             PlayerMove::TeleportChanneling => player.cooldown_teleport <= 0,
             &PlayerMove::TeleportActivate { teleport_to } => {
                 player.is_channeling
-                    && distance(player.pos, teleport_to) <= 3
-                    && self.map.is_in_bounds(teleport_to)
-                    && !self.map.walls.contains(&teleport_to)
+                    && distance(player.pos, teleport_to) <= player.stats.teleport_range
+                    && self.is_walkable(teleport_to)
+                    && !self.is_blocked_by_trail(teleport_to)
             }
             PlayerMove::Throw { direction } => {
                 player.mushrooms > 0 && direction.x.abs() + direction.y.abs() == 1
             }
+            &PlayerMove::BuildWall { direction } => {
+                player.cooldown_wall <= 0
+                    && direction.x.abs() + direction.y.abs() == 1
+                    && self.can_build_wall(player.pos + direction)
+            }
         }
     }
 
+    /// Checks whether a static or a temporary wall occupies the tile.
+    pub fn is_wall(&self, pos: vec2<ICoord>) -> bool {
+        self.map.walls.contains(&pos)
+            || self.temporary_walls.iter().any(|wall| wall.position == pos)
+    }
+
+    /// Checks whether players can stand on the tile.
+    pub fn is_walkable(&self, pos: vec2<ICoord>) -> bool {
+        self.map.is_in_bounds(pos) && !self.is_wall(pos) && self.map.terrain(pos).is_walkable()
+    }
+
+    /// The tile a player is forced to slide onto after stepping from `from` onto ice at `to`.
+    pub fn ice_slide(&self, from: vec2<ICoord>, to: vec2<ICoord>) -> Option<vec2<ICoord>> {
+        if self.map.terrain(to) != Terrain::Ice {
+            return None;
+        }
+        let slide = to + (to - from);
+        (self.is_walkable(slide) && !self.is_blocked_by_trail(slide)).then_some(slide)
+    }
+
+    /// Walks along the path and returns the speed it costs
+    /// and the tile the player has to slide onto next, if any.
+    /// Returns `None` if the path skips a forced slide on ice.
+    pub fn walk_path(&self, path: &[vec2<ICoord>]) -> Option<(usize, Option<vec2<ICoord>>)> {
+        let mut cost = 0;
+        let mut slide = None;
+        for (&from, &to) in path.iter().tuple_windows() {
+            match slide.take() {
+                Some(slide) if slide != to => return None,
+                Some(_) => {} // Sliding is free
+                None => {
+                    cost += self.map.terrain(to).move_cost();
+                    slide = self.ice_slide(from, to);
+                }
+            }
+        }
+        Some((cost, slide))
+    }
+
+    /// Checks whether a trail left on the tile blocks movement.
+    pub fn is_blocked_by_trail(&self, pos: vec2<ICoord>) -> bool {
+        self.rules.trail_blocks
+            && self
+                .trails
+                .iter()
+                .any(|trail| trail.pos == pos && trail.turns_left >= 0)
+    }
+
+    /// Walls can only be built on empty floor tiles.
+    pub fn can_build_wall(&self, pos: vec2<ICoord>) -> bool {
+        self.is_walkable(pos)
+            && !self.is_blocked_by_trail(pos)
+            && !self.bases.contains(&pos)
+            && !self.players.values().any(|player| player.pos == pos)
+            && !self
+                .mushrooms
+                .iter()
+                .any(|mushroom| mushroom.position == pos)
+    }
+
     pub fn validate_path(
         &self,
         player_id: ClientId,
@@ -535,7 +1329,10 @@ impl SharedModel {
             sprint = false;
         }
 
-        if path.len() > player.speed(sprint) + 1 {
+        let Some((cost, None)) = self.walk_path(path) else {
+            return false; // Path stops short of a slide on ice
+        };
+        if cost > player.speed(sprint) {
             return false; // Path exceed player's speed
         }
 
@@ -543,10 +1340,9 @@ impl SharedModel {
             return false; // Path does not start at player's position
         }
 
-        // Check adjacency, walls, and bounds
+        // Check adjacency, walls, terrain, and bounds
         for (&from, &to) in path.iter().tuple_windows() {
-            if self.map.walls.contains(&to) || !are_adjacent(from, to) || !self.map.is_in_bounds(to)
-            {
+            if !self.is_walkable(to) || self.is_blocked_by_trail(to) || !are_adjacent(from, to) {
                 return false;
             }
         }
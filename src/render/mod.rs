@@ -1,7 +1,15 @@
 use crate::{
     assets::*,
+    controls::{self, Action, Controls},
     game::GameUi,
-    model::{client::ClientModel, particles::ParticleKind, shared::Phase, *},
+    model::{
+        client::{ClientModel, ReachableOverlay},
+        history::{self, HistoryLine},
+        particles::ParticleKind,
+        shared::Phase,
+        *,
+    },
+    ui::WidgetState,
 };
 
 use geng::prelude::{itertools::Itertools, *};
@@ -13,6 +21,7 @@ pub struct GameRender {
     geng: Geng,
     assets: Rc<Assets>,
     pixel_texture: ugli::Texture,
+    minimap_texture: ugli::Texture,
 }
 
 impl GameRender {
@@ -21,6 +30,7 @@ impl GameRender {
             geng: geng.clone(),
             assets: assets.clone(),
             pixel_texture: geng_utils::texture::new_texture(geng.ugli(), vec2(1, 1)),
+            minimap_texture: geng_utils::texture::new_texture(geng.ugli(), vec2(1, 1)),
         }
     }
 
@@ -105,13 +115,50 @@ impl GameRender {
                         .get(variant)
                         .unwrap_or(sprites.tiles.first().unwrap())
                 };
+                let terrain = get_terrain_sprite(&sprites.terrain, map.terrain(pos))
+                    .filter(|_| !map.walls.contains(&pos));
                 let pos = map.tile_bounds(pos).as_f32();
-                geng_utils::texture::DrawTexture::new(tile)
+                for tile in std::iter::once(tile).chain(terrain) {
+                    geng_utils::texture::DrawTexture::new(tile)
+                        .fit(pos, vec2(0.5, 0.5))
+                        .draw(&model.camera, &self.geng, framebuffer);
+                }
+            }
+        }
+
+        // Territory
+        if model.shared.rules.territory {
+            for (pos, owner) in model.shared.territory.iter() {
+                let owner = owner.or_else(|| model.fog_memory.territory.get(&pos).copied());
+                let Some(player) = owner.and_then(|id| {
+                    model
+                        .shared
+                        .players
+                        .get(&id)
+                        .or_else(|| model.shared.hidden_players.get(&id))
+                }) else {
+                    continue;
+                };
+                let mut color = player.customization.color;
+                color.a = 0.4;
+                let pos = map.tile_bounds(pos).as_f32();
+                geng_utils::texture::DrawTexture::new(&self.assets.sprites.highlight_tile)
                     .fit(pos, vec2(0.5, 0.5))
+                    .colored(color)
                     .draw(&model.camera, &self.geng, framebuffer);
             }
         }
 
+        // Temporary walls
+        for wall in &model.shared.temporary_walls {
+            let t = (wall.turns_left as f32 / shared::WALL_DURATION as f32).clamp(0.0, 1.0);
+            let pos = map.tile_bounds(wall.position).as_f32();
+            geng_utils::texture::DrawTexture::new(&sprites.wall)
+                .fit(pos, vec2(0.5, 0.5))
+                .colored(Rgba::new(1.0, 1.0, 1.0, 0.4 + 0.6 * t))
+                .draw(&model.camera, &self.geng, framebuffer);
+        }
+
         // Tile highlights
         if let Some(player) = model.shared.players.get(&model.player_id)
             && player.is_channeling
@@ -120,16 +167,12 @@ impl GameRender {
                 PlayerMove::TeleportActivate { teleport_to } => teleport_to,
                 _ => player.pos,
             };
-            let range = 3;
+            let range = player.stats.teleport_range;
             for dx in -range..=range {
                 let range = range - dx.abs();
                 for dy in -range..=range {
                     let pos = player.pos + vec2(dx, dy);
-                    if player.pos != pos
-                        && target != pos
-                        && map.is_in_bounds(pos)
-                        && !map.walls.contains(&pos)
-                    {
+                    if player.pos != pos && target != pos && model.shared.is_walkable(pos) {
                         let tile = &self.assets.sprites.highlight_tile;
                         let pos = map.tile_bounds(pos).as_f32();
                         geng_utils::texture::DrawTexture::new(tile)
@@ -138,6 +181,48 @@ impl GameRender {
                     }
                 }
             }
+
+            // Keyboard cursor
+            if let Some(cursor) = model.key_cursor {
+                let pos = map.tile_bounds(cursor).as_f32();
+                geng_utils::texture::DrawTexture::new(&self.assets.sprites.highlight_tile)
+                    .fit(pos, vec2(0.5, 0.5))
+                    .colored(Rgba::try_from("#E5BD85").unwrap())
+                    .draw(&model.camera, &self.geng, framebuffer);
+            }
+        } else if let Some(player) = model.shared.players.get(&model.player_id)
+            && let Phase::Planning { .. } = model.shared.phase
+            && player.stunned_duration.is_none()
+            && matches!(player.submitted_move, PlayerMove::Normal { .. })
+            && let Some(ReachableOverlay { walk, sprint, .. }) = &model.reachable
+        {
+            // Reachable tiles
+            let walk_color = Rgba::new(1.0, 1.0, 1.0, 0.5);
+            let sprint_color = Rgba {
+                a: 0.5,
+                ..Rgba::try_from("#E5BD85").unwrap()
+            };
+            let mut tiles: Vec<(vec2<ICoord>, Rgba<f32>)> =
+                walk.costs.keys().map(|&pos| (pos, walk_color)).collect();
+            if let Some(sprint) = sprint {
+                tiles.extend(
+                    sprint
+                        .costs
+                        .keys()
+                        .filter(|&&pos| !walk.contains(pos))
+                        .map(|&pos| (pos, sprint_color)),
+                );
+            }
+            for (pos, color) in tiles {
+                if pos == player.pos {
+                    continue;
+                }
+                let pos = map.tile_bounds(pos).as_f32();
+                geng_utils::texture::DrawTexture::new(&self.assets.sprites.highlight_tile)
+                    .fit(pos, vec2(0.5, 0.5))
+                    .colored(color)
+                    .draw(&model.camera, &self.geng, framebuffer);
+            }
         }
 
         // Base
@@ -150,22 +235,66 @@ impl GameRender {
 
         // Mushrooms
         for mushroom in &model.shared.mushrooms {
-            let pos = map.tile_bounds(mushroom.position).as_f32();
+            let pos = model.mushroom_bounds(mushroom).as_f32();
             geng_utils::texture::DrawTexture::new(&self.assets.sprites.mushroom)
                 .fit(pos, vec2(0.5, 0.5))
                 .draw(&model.camera, &self.geng, framebuffer);
         }
 
+        // Hazards
+        for hazard in &model.shared.hazards {
+            let texture = match hazard.kind {
+                shared::HazardKind::Boulder => &sprites.hazards.boulder,
+                shared::HazardKind::Beetle => &sprites.hazards.beetle,
+            };
+            let pos = map.tile_bounds(hazard.position).as_f32();
+            geng_utils::texture::DrawTexture::new(texture)
+                .fit(pos, vec2(0.5, 0.5))
+                .transformed(mat3::rotate(hazard.direction.as_f32().arg()))
+                .draw(&model.camera, &self.geng, framebuffer);
+        }
+
+        // Upcoming environmental event
+        if let Phase::Planning { .. } = model.shared.phase
+            && let Some(event) = &model.shared.next_event
+        {
+            match event {
+                shared::EnvironmentEvent::MushroomRain(positions) => {
+                    for &pos in positions {
+                        let pos = map.tile_bounds(pos).as_f32();
+                        geng_utils::texture::DrawTexture::new(&sprites.mushroom)
+                            .fit(pos, vec2(0.5, 0.5))
+                            .colored(Rgba::new(1.0, 1.0, 1.0, 0.4))
+                            .draw(&model.camera, &self.geng, framebuffer);
+                    }
+                }
+                &shared::EnvironmentEvent::Boulder {
+                    position,
+                    direction,
+                } => {
+                    let pos = map.tile_bounds(position).as_f32();
+                    geng_utils::texture::DrawTexture::new(&sprites.hazards.boulder)
+                        .fit(pos, vec2(0.5, 0.5))
+                        .transformed(mat3::rotate(direction.as_f32().arg()))
+                        .colored(Rgba::new(1.0, 1.0, 1.0, 0.4))
+                        .draw(&model.camera, &self.geng, framebuffer);
+                }
+            }
+        }
+
         // Trails
         for trail in &model.shared.trails {
+            // Trails of players who left still stun, so they stay visible in gray
             let color = model
                 .shared
-                .players
-                .get(&trail.player)
-                .map(|player| player.customization.color)
-                .unwrap_or(Rgba::MAGENTA);
+                .all_players()
+                .find(|player| player.id == trail.player)
+                .map_or(Rgba::try_from("#808080").unwrap(), |player| {
+                    player.customization.color
+                });
 
-            let (texture, rotation, flip) = get_trail_render(&sprites.trail, trail);
+            let (texture, rotation, flip, alpha) =
+                get_trail_render(&sprites.trail, trail, model.shared.rules.trail_duration);
 
             let pos = map.tile_bounds(trail.pos).as_f32();
             geng_utils::texture::DrawTexture::new(texture)
@@ -173,15 +302,25 @@ impl GameRender {
                 .transformed(
                     mat3::rotate(rotation) * mat3::scale(vec2(1.0, if flip { -1.0 } else { 1.0 })),
                 )
-                .colored(color)
+                .colored(Rgba {
+                    a: color.a * alpha,
+                    ..color
+                })
                 .draw(&model.camera, &self.geng, framebuffer);
         }
 
         // Players
         for player in model.shared.players.values() {
-            let color = player.customization.color;
+            let mut color = player.customization.color;
+            if map.terrain(player.pos) == Terrain::Bush {
+                // Only sent to the players next to the bush
+                color.a *= 0.6;
+            }
+            if let Some(tween) = model.player_tweens.get(&player.id) {
+                color.a *= tween.alpha();
+            }
             let texture = get_character_sprite(&sprites.characters, player.customization.character);
-            let player_pos = map.tile_bounds(player.pos).as_f32();
+            let player_pos = model.player_bounds(player).as_f32();
             geng_utils::texture::DrawTexture::new(texture)
                 .fit(player_pos, vec2(0.5, 0.5))
                 .colored(color)
@@ -269,21 +408,80 @@ impl GameRender {
             }
         }
 
+        // Fog of war
+        if let Some(vision) = &model.vision {
+            for (pos, &visible) in vision.iter() {
+                if !visible {
+                    self.geng.draw2d().quad(
+                        framebuffer,
+                        &model.camera,
+                        map.tile_bounds(pos).as_f32(),
+                        Rgba::try_from("#1A151Faa").unwrap(),
+                    );
+                }
+            }
+
+            // Last known information
+            let faded = Rgba::new(1.0, 1.0, 1.0, 0.3);
+            for &pos in &model.fog_memory.mushrooms {
+                if model.is_fogged(pos) {
+                    let pos = map.tile_bounds(pos).as_f32();
+                    geng_utils::texture::DrawTexture::new(&sprites.mushroom)
+                        .fit(pos, vec2(0.5, 0.5))
+                        .colored(faded)
+                        .draw(&model.camera, &self.geng, framebuffer);
+                }
+            }
+            for (player_id, &pos) in &model.fog_memory.players {
+                if let Some(player) = model.shared.all_players().find(|p| p.id == *player_id)
+                    && !model.shared.players.contains_key(player_id)
+                    && model.is_fogged(pos)
+                {
+                    let texture =
+                        get_character_sprite(&sprites.characters, player.customization.character);
+                    let pos = map.tile_bounds(pos).as_f32();
+                    geng_utils::texture::DrawTexture::new(texture)
+                        .fit(pos, vec2(0.5, 0.5))
+                        .colored(Rgba {
+                            a: 0.3,
+                            ..player.customization.color
+                        })
+                        .draw(&model.camera, &self.geng, framebuffer);
+                }
+            }
+        }
+
         // Planned move
-        if let Some(player) = model.shared.players.get(&model.player_id) {
+        for player in
+            model.shared.players.values().filter(|player| {
+                player.id == model.player_id || model.spectating && model.show_moves
+            })
+        {
+            let ghost_color = if player.id == model.player_id {
+                Rgba::try_from("#393b42").unwrap()
+            } else {
+                Rgba {
+                    a: 0.6,
+                    ..player.customization.color
+                }
+            };
             // Path
             let path = match &player.submitted_move {
                 PlayerMove::Normal { path, .. } => Some(path.clone()),
                 &PlayerMove::Throw { direction } => Some(
-                    (0..=shared::THROW_SPEED)
+                    (0..=player.throw_speed())
                         .map(|i| player.pos + direction * i as ICoord)
                         .collect(),
                 ),
                 _ => None,
             };
             if let Some(path) = path {
-                let skip = match model.shared.phase {
-                    Phase::Resolution { .. } => {
+                // Hide the part of the path already walked
+                let skip = match (&model.shared.phase, &player.submitted_move) {
+                    (Phase::Resolution { .. }, PlayerMove::Normal { .. }) => {
+                        player.resolution_path_index
+                    }
+                    (Phase::Resolution { .. }, _) => {
                         player.resolution_speed_max - player.resolution_speed_left
                     }
                     _ => 0,
@@ -294,11 +492,13 @@ impl GameRender {
                         pos: at,
                         connection_from: Some(from),
                         connection_to: to,
+                        turns_left: model.shared.rules.trail_duration,
                     };
 
-                    let color = Rgba::try_from("#393b42").unwrap();
+                    let color = ghost_color;
 
-                    let (texture, rotation, flip) = get_trail_render(&sprites.trail, trail);
+                    let (texture, rotation, flip, _) =
+                        get_trail_render(&sprites.trail, trail, model.shared.rules.trail_duration);
 
                     let pos = map.tile_bounds(at).as_f32();
                     geng_utils::texture::DrawTexture::new(texture)
@@ -319,7 +519,12 @@ impl GameRender {
                 &PlayerMove::Throw { direction }
                     if matches!(model.shared.phase, Phase::Planning { .. }) =>
                 {
-                    Some(player.pos + direction * shared::THROW_SPEED as ICoord)
+                    Some(player.pos + direction * player.throw_speed() as ICoord)
+                }
+                &PlayerMove::BuildWall { direction }
+                    if matches!(model.shared.phase, Phase::Planning { .. }) =>
+                {
+                    Some(player.pos + direction)
                 }
                 _ => None,
             };
@@ -328,12 +533,13 @@ impl GameRender {
             {
                 let texture = match player.submitted_move {
                     PlayerMove::Throw { .. } => &sprites.mushroom,
+                    PlayerMove::BuildWall { .. } => &sprites.wall,
                     _ => get_character_sprite(&sprites.characters, player.customization.character),
                 };
                 let pos = map.tile_bounds(pos).as_f32();
                 geng_utils::texture::DrawTexture::new(texture)
                     .fit(pos, vec2(0.5, 0.5))
-                    .colored(Rgba::try_from("#393b42").unwrap())
+                    .colored(ghost_color)
                     .draw(&model.camera, &self.geng, framebuffer);
             }
         }
@@ -356,6 +562,394 @@ impl GameRender {
         self.draw_pixels(model, framebuffer);
     }
 
+    fn draw_spectator_ui(
+        &self,
+        model: &ClientModel,
+        ui: &GameUi,
+        framebuffer: &mut ugli::Framebuffer,
+    ) {
+        let text_color = Rgba::try_from("#B4A091").unwrap();
+        for (player_id, widget) in &ui.players {
+            let Some(player) = model.shared.players.get(player_id) else {
+                continue;
+            };
+            let position = widget.position;
+            if model.follow == Some(*player_id) || widget.hovered {
+                self.geng.draw2d().quad(
+                    framebuffer,
+                    &geng::PixelPerfectCamera,
+                    position,
+                    Rgba::try_from("#42343Baa").unwrap(),
+                );
+            }
+
+            let icon = Aabb2::point(position.top_left())
+                .extend_right(position.height())
+                .extend_down(position.height());
+            geng_utils::texture::DrawTexture::new(get_character_sprite(
+                &self.assets.sprites.characters,
+                player.customization.character,
+            ))
+            .fit(icon, vec2(0.5, 0.5))
+            .colored(player.customization.color)
+            .draw(&geng::PixelPerfectCamera, &self.geng, framebuffer);
+
+            let info = Aabb2::from_corners(
+                vec2(icon.max.x + position.height() * 0.2, position.min.y),
+                position.max,
+            );
+            let name = Aabb2::from_corners(vec2(info.min.x, info.center().y), info.max)
+                .extend_uniform(-info.height() * 0.05);
+            let stats = Aabb2::from_corners(info.min, vec2(info.max.x, info.center().y))
+                .extend_uniform(-info.height() * 0.05);
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &draw2d::Text::unit(
+                    self.assets.font.clone(),
+                    format!("{} - {}", player.customization.name, player.score),
+                    player.customization.color,
+                )
+                .align_bounding_box(vec2(0.0, 0.5))
+                .fit_into(name),
+            );
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &draw2d::Text::unit(
+                    self.assets.font.clone(),
+                    format!(
+                        "mush {} spr {} tp {} wall {}",
+                        player.mushrooms,
+                        player.cooldown_sprint.max(0),
+                        player.cooldown_teleport.max(0),
+                        player.cooldown_wall.max(0),
+                    ),
+                    text_color,
+                )
+                .align_bounding_box(vec2(0.0, 0.5))
+                .fit_into(stats),
+            );
+        }
+
+        let toggle = &ui.show_moves;
+        self.geng.draw2d().draw2d(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            &draw2d::Text::unit(
+                self.assets.font.clone(),
+                if model.show_moves {
+                    "Hide moves"
+                } else {
+                    "Show moves"
+                },
+                if toggle.hovered {
+                    Rgba::try_from("#5590B4").unwrap()
+                } else {
+                    text_color
+                },
+            )
+            .fit_into(toggle.position),
+        );
+
+        let join = &ui.join;
+        self.geng.draw2d().draw2d(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            &draw2d::Text::unit(
+                self.assets.font.clone(),
+                if model.joining {
+                    "Joining next match.."
+                } else {
+                    "Join"
+                },
+                if join.hovered && !model.joining {
+                    Rgba::try_from("#5590B4").unwrap()
+                } else {
+                    text_color
+                },
+            )
+            .fit_into(join.position),
+        );
+    }
+
+    fn draw_history(&self, model: &ClientModel, ui: &GameUi, framebuffer: &mut ugli::Framebuffer) {
+        let text_color = Rgba::try_from("#B4A091").unwrap();
+        let toggle = &ui.history_toggle;
+        self.geng.draw2d().draw2d(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            &draw2d::Text::unit(
+                self.assets.font.clone(),
+                if ui.history_open {
+                    "Hide history"
+                } else {
+                    "History"
+                },
+                if toggle.hovered {
+                    Rgba::try_from("#5590B4").unwrap()
+                } else {
+                    text_color
+                },
+            )
+            .align_bounding_box(vec2(0.0, 0.5))
+            .fit_into(toggle.position),
+        );
+        if !ui.history_open {
+            return;
+        }
+
+        self.geng.draw2d().quad(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            ui.history.position,
+            Rgba::try_from("#1A151Faa").unwrap(),
+        );
+        let lines = history::lines(&model.history);
+        if lines.is_empty()
+            && let Some(row) = ui.history_rows.first()
+        {
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &draw2d::Text::unit(self.assets.font.clone(), "Nothing happened yet", text_color)
+                    .align_bounding_box(vec2(0.0, 0.5))
+                    .fit_into(row.position.extend_uniform(-row.position.height() * 0.1)),
+            );
+        }
+        for (row, line) in ui
+            .history_rows
+            .iter()
+            .zip(lines.iter().skip(ui.history_scroll))
+        {
+            let text_pos = row.position.extend_uniform(-row.position.height() * 0.1);
+            let (text, color) = match line {
+                HistoryLine::Turn(turn) => {
+                    (format!("Turn {turn}"), Rgba::try_from("#E5BD85").unwrap())
+                }
+                HistoryLine::Entry(entry) => {
+                    if row.hovered {
+                        self.geng.draw2d().quad(
+                            framebuffer,
+                            &geng::PixelPerfectCamera,
+                            row.position,
+                            Rgba::try_from("#42343Baa").unwrap(),
+                        );
+                        // Involved tiles
+                        for &pos in &entry.tiles {
+                            let pos = model.shared.map.tile_bounds(pos).as_f32();
+                            geng_utils::texture::DrawTexture::new(
+                                &self.assets.sprites.highlight_tile,
+                            )
+                            .fit(pos, vec2(0.5, 0.5))
+                            .colored(Rgba::try_from("#E5BD85").unwrap())
+                            .draw(
+                                &model.camera,
+                                &self.geng,
+                                framebuffer,
+                            );
+                        }
+                    }
+                    let color = entry
+                        .player()
+                        .and_then(|player_id| {
+                            model
+                                .shared
+                                .all_players()
+                                .find(|player| player.id == player_id)
+                        })
+                        .map_or(text_color, |player| player.customization.color);
+                    (entry.describe(&model.shared), color)
+                }
+            };
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &draw2d::Text::unit(self.assets.font.clone(), text, color)
+                    .align_bounding_box(vec2(0.0, 0.5))
+                    .fit_into(text_pos),
+            );
+        }
+    }
+
+    /// Draws the map overview with one pixel per tile into the minimap widget.
+    pub fn draw_minimap(
+        &mut self,
+        model: &ClientModel,
+        widget: &WidgetState,
+        framebuffer: &mut ugli::Framebuffer,
+    ) {
+        let map = &model.shared.map;
+        let size = (map.bounds.size() + vec2(1, 1)).map(|x| x.max(1) as usize);
+        geng_utils::texture::update_texture_size(&mut self.minimap_texture, size, self.geng.ugli());
+        self.minimap_texture.set_filter(ugli::Filter::Nearest);
+
+        {
+            let texture = &mut geng_utils::texture::attach_texture(
+                &mut self.minimap_texture,
+                self.geng.ugli(),
+            );
+            ugli::clear(
+                texture,
+                Some(Rgba::try_from("#36274B").unwrap()),
+                None,
+                None,
+            );
+            let tile = |pos: vec2<ICoord>| {
+                Aabb2::point((pos - map.bounds.min).as_f32()).extend_positive(vec2(1.0, 1.0))
+            };
+
+            // Map
+            for (pos, &terrain) in map.terrain.iter() {
+                if terrain == Terrain::Water {
+                    self.geng.draw2d().quad(
+                        texture,
+                        &geng::PixelPerfectCamera,
+                        tile(pos),
+                        Rgba::try_from("#474C80").unwrap(),
+                    );
+                }
+            }
+            let wall_color = Rgba::try_from("#6D767B").unwrap();
+            for &pos in map.walls.iter().chain(
+                model
+                    .shared
+                    .temporary_walls
+                    .iter()
+                    .map(|wall| &wall.position),
+            ) {
+                self.geng
+                    .draw2d()
+                    .quad(texture, &geng::PixelPerfectCamera, tile(pos), wall_color);
+            }
+            for &pos in &model.shared.bases {
+                self.geng.draw2d().quad(
+                    texture,
+                    &geng::PixelPerfectCamera,
+                    tile(pos),
+                    Rgba::try_from("#B4A091").unwrap(),
+                );
+            }
+
+            // Fog of war
+            if let Some(vision) = &model.vision {
+                for (pos, &visible) in vision.iter() {
+                    if !visible {
+                        self.geng.draw2d().quad(
+                            texture,
+                            &geng::PixelPerfectCamera,
+                            tile(pos),
+                            Rgba::try_from("#1A151Faa").unwrap(),
+                        );
+                    }
+                }
+            }
+
+            // Mushrooms
+            let mushroom_color = Rgba::try_from("#E5BD85").unwrap();
+            for mushroom in &model.shared.mushrooms {
+                self.geng.draw2d().quad(
+                    texture,
+                    &geng::PixelPerfectCamera,
+                    tile(mushroom.position),
+                    mushroom_color,
+                );
+            }
+            for &pos in &model.fog_memory.mushrooms {
+                if model.is_fogged(pos) {
+                    self.geng.draw2d().quad(
+                        texture,
+                        &geng::PixelPerfectCamera,
+                        tile(pos),
+                        Rgba {
+                            a: 0.5,
+                            ..mushroom_color
+                        },
+                    );
+                }
+            }
+
+            // Players
+            for player in model.shared.players.values() {
+                self.geng.draw2d().quad(
+                    texture,
+                    &geng::PixelPerfectCamera,
+                    tile(player.pos),
+                    player.customization.color,
+                );
+            }
+            for (player_id, &pos) in &model.fog_memory.players {
+                if let Some(player) = model.shared.all_players().find(|p| p.id == *player_id)
+                    && !model.shared.players.contains_key(player_id)
+                    && model.is_fogged(pos)
+                {
+                    self.geng.draw2d().quad(
+                        texture,
+                        &geng::PixelPerfectCamera,
+                        tile(pos),
+                        Rgba {
+                            a: 0.5,
+                            ..player.customization.color
+                        },
+                    );
+                }
+            }
+        }
+
+        self.geng.draw2d().quad(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            widget.position,
+            Rgba::try_from("#1A151Faa").unwrap(),
+        );
+        let bounds = minimap_bounds(widget.position, map);
+        self.geng.draw2d().textured_quad(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            bounds,
+            &self.minimap_texture,
+            Rgba::WHITE,
+        );
+
+        // Camera view
+        let world = map.world_bounds().as_f32();
+        let to_minimap =
+            |pos: vec2<f32>| bounds.min + (pos - world.min) / world.size() * bounds.size();
+        let framebuffer_size = framebuffer.size().as_f32();
+        let clamp = |pos: vec2<f32>| {
+            vec2(
+                pos.x.clamp(bounds.min.x, bounds.max.x),
+                pos.y.clamp(bounds.min.y, bounds.max.y),
+            )
+        };
+        let view = Aabb2::from_corners(
+            clamp(to_minimap(
+                model.camera.screen_to_world(framebuffer_size, vec2::ZERO),
+            )),
+            clamp(to_minimap(
+                model
+                    .camera
+                    .screen_to_world(framebuffer_size, framebuffer_size),
+            )),
+        );
+        let width = (bounds.height() * 0.02).max(1.0);
+        let color = if widget.hovered {
+            Rgba::try_from("#5590B4").unwrap()
+        } else {
+            Rgba::try_from("#B4A091").unwrap()
+        };
+        for side in [
+            view.extend_left(-view.width() + width),
+            view.extend_right(-view.width() + width),
+            view.extend_down(-view.height() + width),
+            view.extend_up(-view.height() + width),
+        ] {
+            self.geng
+                .draw2d()
+                .quad(framebuffer, &geng::PixelPerfectCamera, side, color);
+        }
+    }
+
     fn draw_pixels(&mut self, model: &ClientModel, final_buffer: &mut ugli::Framebuffer) {
         let framebuffer =
             &mut geng_utils::texture::attach_texture(&mut self.pixel_texture, self.geng.ugli());
@@ -385,78 +979,74 @@ impl GameRender {
         &self,
         model: &ClientModel,
         ui: &GameUi,
+        controls: &Controls,
         framebuffer: &mut ugli::Framebuffer,
     ) {
         if let Some(player) = model.shared.players.get(&model.player_id) {
             // Abilities
-            let active = player.stunned_duration.is_none() && player.cooldown_sprint <= 0;
-            let texture = if !active {
-                &self.assets.sprites.abilities.sprint_disable
-            } else {
-                &self.assets.sprites.abilities.sprint
-            };
-            let feedback = if active && ui.ability_sprint.hovered
-                || matches!(
-                    player.submitted_move,
-                    PlayerMove::Normal { sprint: true, .. }
-                ) {
-                ui.ability_sprint.position.width() * 0.1
-            } else {
-                0.0
-            };
-            self.geng.draw2d().textured_quad(
-                framebuffer,
-                &geng::PixelPerfectCamera,
-                ui.ability_sprint.position.extend_uniform(feedback),
-                texture,
-                Rgba::WHITE,
-            );
-
-            let active = player.stunned_duration.is_none() && player.cooldown_teleport <= 0;
-            let texture = if !active {
-                &self.assets.sprites.abilities.teleport_disable
-            } else {
-                &self.assets.sprites.abilities.teleport
-            };
-            let feedback = if active && ui.ability_teleport.hovered
-                || matches!(
-                    player.submitted_move,
-                    PlayerMove::TeleportChanneling | PlayerMove::TeleportActivate { .. }
-                )
-                || player.is_channeling
-            {
-                ui.ability_teleport.position.width() * 0.1
-            } else {
-                0.0
-            };
-            self.geng.draw2d().textured_quad(
-                framebuffer,
-                &geng::PixelPerfectCamera,
-                ui.ability_teleport.position.extend_uniform(feedback),
-                texture,
-                Rgba::WHITE,
-            );
+            for (slot, (ability, widget)) in ui.abilities.iter().enumerate() {
+                let (active, selected) = match ability {
+                    Ability::Sprint => (
+                        player.cooldown_sprint <= 0,
+                        matches!(
+                            player.submitted_move,
+                            PlayerMove::Normal { sprint: true, .. }
+                        ),
+                    ),
+                    Ability::Teleport => (
+                        player.cooldown_teleport <= 0,
+                        matches!(
+                            player.submitted_move,
+                            PlayerMove::TeleportChanneling | PlayerMove::TeleportActivate { .. }
+                        ) || player.is_channeling,
+                    ),
+                    Ability::Throw => (
+                        player.mushrooms > 0,
+                        matches!(player.submitted_move, PlayerMove::Throw { .. }),
+                    ),
+                    Ability::Wall => (
+                        player.cooldown_wall <= 0,
+                        matches!(player.submitted_move, PlayerMove::BuildWall { .. }),
+                    ),
+                };
+                let active = active && player.stunned_duration.is_none();
+                let texture = get_ability_sprite(&self.assets.sprites.abilities, *ability, active);
+                let feedback = if active && widget.hovered || selected {
+                    widget.position.width() * 0.1
+                } else {
+                    0.0
+                };
+                let position = widget.position.extend_uniform(feedback);
+                self.geng.draw2d().textured_quad(
+                    framebuffer,
+                    &geng::PixelPerfectCamera,
+                    position,
+                    texture,
+                    Rgba::WHITE,
+                );
 
-            let active = player.stunned_duration.is_none() && player.mushrooms > 0;
-            let texture = if !active {
-                &self.assets.sprites.abilities.throw_disable
-            } else {
-                &self.assets.sprites.abilities.throw
-            };
-            let feedback = if active && ui.ability_throw.hovered
-                || matches!(player.submitted_move, PlayerMove::Throw { .. })
-            {
-                ui.ability_throw.position.width() * 0.1
-            } else {
-                0.0
-            };
-            self.geng.draw2d().textured_quad(
-                framebuffer,
-                &geng::PixelPerfectCamera,
-                ui.ability_throw.position.extend_uniform(feedback),
-                texture,
-                Rgba::WHITE,
-            );
+                // Hotkey
+                let hotkey = position
+                    .extend_down(-position.height() * 23.0 / 28.0)
+                    .extend_uniform(-position.height() / 28.0);
+                self.geng.draw2d().draw2d(
+                    framebuffer,
+                    &geng::PixelPerfectCamera,
+                    &draw2d::Text::unit(
+                        self.assets.font.clone(),
+                        controls
+                            .keys(Action::Ability(slot))
+                            .first()
+                            .map_or(String::new(), |&key| controls::key_name(key)),
+                        if active {
+                            Rgba::try_from("#5590B4").unwrap()
+                        } else {
+                            Rgba::try_from("#36274B").unwrap()
+                        },
+                    )
+                    .fit_into(hotkey),
+                );
+            }
 
             // Mushrooms
             self.geng.draw2d().textured_quad(
@@ -467,7 +1057,8 @@ impl GameRender {
                 Rgba::WHITE,
             );
             let mushroom_size = ui.mushrooms.position.size() * vec2(0.2, 1.0);
-            for i in 0..5 {
+            let slots = player.stats.mushroom_capacity.unwrap_or(5).min(5);
+            for i in 0..slots {
                 let pos = Aabb2::point(
                     ui.mushrooms.position.bottom_left() + vec2(mushroom_size.x * i as f32, 0.0),
                 )
@@ -669,8 +1260,7 @@ impl GameRender {
         let total_height = score_height + character_height + name_height + spacing;
         for (i, player) in model
             .shared
-            .players
-            .values()
+            .all_players()
             .sorted_by_key(|player| player.id)
             .enumerate()
         {
@@ -704,39 +1294,173 @@ impl GameRender {
             );
         }
 
+        if model.spectating {
+            self.draw_spectator_ui(model, ui, framebuffer);
+        }
+        self.draw_history(model, ui, framebuffer);
+
         if let Phase::Results { .. } = model.shared.phase {
-            // Winner
-            if let Some(player) = model
-                .shared
-                .players
-                .values()
-                .max_by_key(|player| player.score)
-            {
-                self.geng.draw2d().quad(
-                    framebuffer,
-                    &geng::PixelPerfectCamera,
-                    Aabb2::point(screen_size / 2.0).extend_symmetric(screen_size / 2.0),
-                    Rgba::try_from("#1A151Faa").unwrap(),
-                );
+            self.draw_results(model, framebuffer);
+        }
+    }
+
+    /// The winner, statistics of every player and the awards of the match.
+    fn draw_results(&self, model: &ClientModel, framebuffer: &mut ugli::Framebuffer) {
+        let Some(winner) = model.shared.all_players().max_by_key(|player| player.score) else {
+            return;
+        };
+
+        let screen_size = framebuffer.size().as_f32();
+        self.geng.draw2d().quad(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            Aabb2::ZERO.extend_positive(screen_size),
+            Rgba::try_from("#1A151Faa").unwrap(),
+        );
+        let text_color = Rgba::try_from("#B4A091").unwrap();
+        let header_color = Rgba::try_from("#E5BD85").unwrap();
+
+        let row_height = screen_size.y / 25.0;
+        let width = (screen_size.x * 0.8).min(row_height * 36.0);
+        let left = (screen_size.x - width) / 2.0;
+        let mut y = screen_size.y * 0.85;
+        let row = |y: f32, height: f32| {
+            Aabb2::point(vec2(left, y))
+                .extend_right(width)
+                .extend_down(height)
+        };
+
+        // Winner
+        self.geng.draw2d().draw2d(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            &draw2d::Text::unit(
+                self.assets.font.clone(),
+                format!("Winner - {} : {}", winner.customization.name, winner.score),
+                text_color,
+            )
+            .fit_into(row(y, row_height * 1.5)),
+        );
+        y -= row_height * 2.5;
+
+        // Statistics
+        let columns = [
+            "Player",
+            "Score",
+            "Picked",
+            "Delivered",
+            "Thrown",
+            "Hits",
+            "Stunned",
+            "Tiles",
+            "Sprint",
+            "Teleports",
+        ];
+        // The name column is three times wider
+        let unit = width / (columns.len() + 2) as f32;
+        let cell = |column: usize, y: f32| {
+            let (x, width) = if column == 0 {
+                (left, unit * 3.0)
+            } else {
+                (left + unit * (column + 2) as f32, unit)
+            };
+            Aabb2::point(vec2(x, y))
+                .extend_right(width)
+                .extend_down(row_height)
+                .extend_uniform(-row_height * 0.15)
+        };
+        let align = |column: usize| if column == 0 { 0.0 } else { 0.5 };
+
+        for (column, title) in columns.into_iter().enumerate() {
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &draw2d::Text::unit(self.assets.font.clone(), title, header_color)
+                    .align_bounding_box(vec2(align(column), 0.5))
+                    .fit_into(cell(column, y)),
+            );
+        }
+        y -= row_height;
+        for player in model
+            .shared
+            .all_players()
+            .sorted_by_key(|player| (std::cmp::Reverse(player.score), player.id))
+        {
+            let stats = &player.match_stats;
+            let values = [
+                player.customization.name.clone(),
+                player.score.to_string(),
+                stats.mushrooms_picked.to_string(),
+                stats.mushrooms_delivered.to_string(),
+                stats.mushrooms_thrown.to_string(),
+                stats.hits_landed.to_string(),
+                stats.times_stunned.to_string(),
+                stats.tiles_walked.to_string(),
+                stats.longest_sprint.to_string(),
+                stats.teleports.to_string(),
+            ];
+            for (column, value) in values.into_iter().enumerate() {
                 self.geng.draw2d().draw2d(
                     framebuffer,
                     &geng::PixelPerfectCamera,
                     &draw2d::Text::unit(
                         self.assets.font.clone(),
-                        format!("Winner - {} : {}", player.customization.name, player.score),
-                        Rgba::try_from("#B4A091").unwrap(),
+                        value,
+                        player.customization.color,
                     )
-                    .align_bounding_box(vec2(0.5, 0.5))
-                    .transform(
-                        mat3::translate(screen_size / 2.0)
-                            * mat3::scale_uniform(screen_size.y / 30.0 * 0.6),
-                    ),
+                    .align_bounding_box(vec2(align(column), 0.5))
+                    .fit_into(cell(column, y)),
                 );
             }
+            y -= row_height;
+        }
+
+        // Awards
+        let awards = MatchStats::awards(model.shared.all_players());
+        if awards.is_empty() {
+            return;
+        }
+        y -= row_height;
+        self.geng.draw2d().draw2d(
+            framebuffer,
+            &geng::PixelPerfectCamera,
+            &draw2d::Text::unit(self.assets.font.clone(), "Awards", header_color)
+                .fit_into(row(y, row_height).extend_uniform(-row_height * 0.1)),
+        );
+        y -= row_height;
+        for award in awards {
+            let Some(player) = model
+                .shared
+                .all_players()
+                .find(|player| player.id == award.player)
+            else {
+                continue;
+            };
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &draw2d::Text::unit(
+                    self.assets.font.clone(),
+                    format!(
+                        "{} - {} ({})",
+                        award.title, player.customization.name, award.value
+                    ),
+                    player.customization.color,
+                )
+                .fit_into(row(y, row_height).extend_uniform(-row_height * 0.15)),
+            );
+            y -= row_height;
         }
     }
 }
 
+/// The part of the minimap widget the map is drawn into, keeping the map aspect ratio.
+pub fn minimap_bounds(position: Aabb2<f32>, map: &Map) -> Aabb2<f32> {
+    let map_size = map.world_bounds().size().as_f32();
+    let scale = (position.width() / map_size.x).min(position.height() / map_size.y);
+    Aabb2::point(position.center()).extend_symmetric(map_size * scale / 2.0)
+}
+
 pub fn get_character_sprite(sprites: &CharacterSprites, character: Character) -> &PixelTexture {
     match character {
         Character::Ant => &sprites.ant,
@@ -760,11 +1484,43 @@ pub fn get_character_sprite(sprites: &CharacterSprites, character: Character) ->
     }
 }
 
+pub fn get_terrain_sprite(sprites: &TerrainSprites, terrain: Terrain) -> Option<&PixelTexture> {
+    match terrain {
+        Terrain::Floor => None,
+        Terrain::Mud => Some(&sprites.mud),
+        Terrain::Ice => Some(&sprites.ice),
+        Terrain::Water => Some(&sprites.water),
+        Terrain::Bush => Some(&sprites.bush),
+    }
+}
+
+pub fn get_ability_sprite(
+    sprites: &AbilitySprites,
+    ability: Ability,
+    active: bool,
+) -> &PixelTexture {
+    match (ability, active) {
+        (Ability::Sprint, true) => &sprites.sprint,
+        (Ability::Sprint, false) => &sprites.sprint_disable,
+        (Ability::Teleport, true) => &sprites.teleport,
+        (Ability::Teleport, false) => &sprites.teleport_disable,
+        (Ability::Throw, true) => &sprites.throw,
+        (Ability::Throw, false) => &sprites.throw_disable,
+        (Ability::Wall, true) => &sprites.wall,
+        (Ability::Wall, false) => &sprites.wall_disable,
+    }
+}
+
+/// Returns the texture, rotation, vertical flip and opacity of the trail.
+/// Persistent trails fade out as they get closer to disappearing.
 fn get_trail_render<'a>(
     sprites: &'a TrailSprites,
     trail: &PlayerTrail,
-) -> (&'a PixelTexture, Angle<f32>, bool) {
-    match trail.connection_from {
+    trail_duration: Turns,
+) -> (&'a PixelTexture, Angle<f32>, bool, f32) {
+    let alpha =
+        ((trail.turns_left + 1) as f32 / (trail_duration.max(0) + 1) as f32).clamp(0.2, 1.0);
+    let (texture, rotation, flip) = match trail.connection_from {
         None => (
             &sprites.initial,
             (trail.connection_to - trail.pos).as_f32().arg(),
@@ -782,5 +1538,40 @@ fn get_trail_render<'a>(
                 (&sprites.corner, from_angle, flip)
             }
         }
+    };
+    (texture, rotation, flip, alpha)
+}
+
+/// Draws the texture to the screen through the CRT shader,
+/// or as is if the effect is turned off.
+pub fn draw_crt(
+    geng: &Geng,
+    assets: &Assets,
+    unit_quad: &ugli::VertexBuffer<draw2d::TexturedVertex>,
+    texture: &ugli::Texture,
+    strength: f32,
+    time: f32,
+    framebuffer: &mut ugli::Framebuffer,
+) {
+    if strength <= 0.0 {
+        geng_utils::texture::DrawTexture::new(texture)
+            .fit_screen(vec2(0.5, 0.5), framebuffer)
+            .draw(&geng::PixelPerfectCamera, geng, framebuffer);
+        return;
     }
+
+    ugli::draw(
+        framebuffer,
+        &assets.shaders.crt,
+        ugli::DrawMode::TriangleFan,
+        unit_quad,
+        ugli::uniforms! {
+            u_texture: texture,
+            u_curvature: 50.0 / strength,
+            u_vignette_multiplier: 0.1 * strength,
+            u_scanlines_multiplier: 0.1 * strength,
+            u_time: time,
+        },
+        ugli::DrawParameters::default(),
+    );
 }
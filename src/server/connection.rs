@@ -8,8 +8,7 @@ pub struct ClientConnection {
 impl Drop for ClientConnection {
     fn drop(&mut self) {
         let mut state = self.state.lock().unwrap();
-        let _client = state.clients.remove(&self.id).unwrap();
-        state.player_disconnect(self.id);
+        state.client_disconnect(self.id);
     }
 }
 
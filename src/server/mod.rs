@@ -14,8 +14,17 @@ pub struct App {
 }
 
 impl App {
-    pub fn new() -> Self {
-        let state = Arc::new(Mutex::new(ServerState::new()));
+    pub fn new(rules: Rules, map: Option<std::path::PathBuf>) -> Self {
+        let name = map.as_ref().and_then(|path| path.file_stem()).map_or_else(
+            || "default".to_owned(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        let map = map.map(|path| {
+            let text = std::fs::read_to_string(&path)
+                .unwrap_or_else(|err| panic!("failed to read map {path:?}: {err}"));
+            Map::parse(&text).unwrap_or_else(|err| panic!("failed to parse map {path:?}: {err}"))
+        });
+        let state = Arc::new(Mutex::new(ServerState::new(name, rules, map)));
         Self {
             state: state.clone(),
             background_thread: std::thread::spawn(move || {
@@ -37,29 +46,11 @@ impl geng::net::server::App for App {
 
     type ClientMessage = ClientMessage;
 
-    fn connect(
-        &mut self,
-        mut sender: Box<dyn geng::net::Sender<Self::ServerMessage>>,
-    ) -> Self::Client {
+    fn connect(&mut self, sender: Box<dyn geng::net::Sender<Self::ServerMessage>>) -> Self::Client {
         let mut state = self.state.lock().unwrap();
-        if state.clients.is_empty() {
-            state.timer.reset();
-        }
-
         let my_id = state.next_id;
         state.next_id += 1;
-
-        sender.send(ServerMessage::Setup(state.new_player(my_id)));
-        sender.send(ServerMessage::Ping);
-        // let token = Alphanumeric.sample_string(&mut thread_rng(), 16);
-        // sender.send(ServerMessage::YourToken(token.clone()));
-
-        let client = Client {
-            // token,
-            sender,
-        };
-
-        state.clients.insert(my_id, client);
+        state.client_connect(my_id, sender);
         ClientConnection {
             id: my_id,
             state: self.state.clone(),
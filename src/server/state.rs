@@ -9,78 +9,245 @@ pub struct Client {
 }
 
 pub struct ServerState {
+    /// Name of the room shown in the server status.
+    pub name: String,
     pub timer: Timer,
+    pub uptime: Timer,
     pub next_id: ClientId,
     pub config: Config,
     pub clients: HashMap<ClientId, Client>,
+    /// Connections that have not joined the game yet.
+    pub pending: HashMap<ClientId, Client>,
     pub model: SharedModel,
     pub queued_moves: HashMap<ClientId, PlayerMove>,
+    /// Spectators waiting for the next match to join it.
+    pub queued_joins: HashMap<ClientId, PlayerCustomization>,
 }
 
 impl ServerState {
     pub const TICKS_PER_SECOND: f32 = 2.0;
 
-    pub fn new() -> Self {
-        let mut map = Map::new(vec2(14, 7));
-        map.walls = vec![vec2(3, 0), vec2(-2, 0)];
+    pub fn new(name: String, rules: Rules, map: Option<Map>) -> Self {
+        let map = map.unwrap_or_else(|| {
+            let mut map = Map::new(vec2(14, 7));
+            map.walls = vec![vec2(3, 0), vec2(-2, 0)];
+            map
+        });
         Self {
+            name,
             timer: Timer::new(),
+            uptime: Timer::new(),
             next_id: 1,
             config: Config {},
             clients: HashMap::new(),
-            model: SharedModel::new(map),
+            pending: HashMap::new(),
+            model: SharedModel::new(map, rules),
             queued_moves: HashMap::new(),
+            queued_joins: HashMap::new(),
+        }
+    }
+
+    /// Registers a new connection.
+    /// The client joins the game with its first message other than a status query.
+    pub fn client_connect(
+        &mut self,
+        client_id: ClientId,
+        sender: Box<dyn geng::net::Sender<ServerMessage>>,
+    ) {
+        self.pending.insert(client_id, Client { sender });
+    }
+
+    /// Turns a pending connection into a player.
+    fn client_join(&mut self, client_id: ClientId) {
+        let Some(mut client) = self.pending.remove(&client_id) else {
+            return;
+        };
+        if self.clients.is_empty() {
+            self.timer.reset();
+        }
+        client
+            .sender
+            .send(ServerMessage::Setup(self.new_player(client_id)));
+        client.sender.send(ServerMessage::Ping);
+        self.clients.insert(client_id, client);
+    }
+
+    pub fn client_disconnect(&mut self, client_id: ClientId) {
+        if self.pending.remove(&client_id).is_none() {
+            self.clients.remove(&client_id);
+            self.player_disconnect(client_id);
+        }
+    }
+
+    pub fn status(&self) -> ServerStatus {
+        let players = self.model.players.len();
+        ServerStatus {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            uptime: self.uptime.elapsed().as_secs_f64() as f32,
+            rooms: vec![RoomStatus {
+                name: self.name.clone(),
+                players,
+                spectators: self.clients.len().saturating_sub(players),
+                phase: self.model.phase.clone(),
+                turn_current: self.model.turn_current,
+                turns_max: self.model.turns_max,
+            }],
         }
     }
 
     pub fn new_player(&mut self, player_id: ClientId) -> Setup {
+        let customization = PlayerCustomization::random();
+        if !self.spawn_player(player_id, customization.clone()) {
+            // No room on the map, the client watches until the next match
+            self.queued_joins.insert(player_id, customization);
+        }
+        self.broadcast(ServerMessage::Sync);
+
+        Setup {
+            player_id,
+            model: self.snapshot_for(player_id),
+        }
+    }
+
+    /// Places the player on a random free tile, returns false if none was found.
+    fn spawn_player(&mut self, player_id: ClientId, customization: PlayerCustomization) -> bool {
         let mut position = None;
         for _ in 0..100 {
             let pos = self.model.map.random_position();
-            if self.model.map.walls.contains(&pos)
-                || self.model.players.values().any(|player| player.pos == pos)
+            if self.model.is_walkable(pos)
+                && !self.model.is_blocked_by_trail(pos)
+                && !self.model.players.values().any(|player| player.pos == pos)
             {
-                continue;
-            } else {
                 position = Some(pos);
+                break;
             }
         }
-        if let Some(position) = position {
-            self.model.players.insert(
-                player_id,
-                Player::new(player_id, PlayerCustomization::random(), position),
-            );
+        let Some(position) = position else {
+            log::warn!("no free tile to spawn player {player_id}");
+            return false;
+        };
+        self.model.players.insert(
+            player_id,
+            Player::new(player_id, customization, position, &self.model.rules),
+        );
+        true
+    }
+
+    /// Lets a spectator play, right away if the match has not started yet,
+    /// or when the next one starts otherwise.
+    pub fn player_join(&mut self, client_id: ClientId, customization: PlayerCustomization) {
+        if self.model.players.contains_key(&client_id) {
+            return;
+        }
+        let customization = sanitize_customization(customization);
+        if (self.model.players.is_empty() || matches!(self.model.phase, Phase::Starting { .. }))
+            && self.spawn_player(client_id, customization.clone())
+        {
+            self.broadcast(ServerMessage::Sync);
+        } else {
+            self.queued_joins.insert(client_id, customization);
         }
+    }
 
-        for client in self.clients.values_mut() {
-            client.sender.send(ServerMessage::Sync(self.model.clone()));
+    /// Spawns the queued players, the ones without a free tile stay queued.
+    fn spawn_queued_players(&mut self) {
+        for (client_id, customization) in std::mem::take(&mut self.queued_joins) {
+            if !self.spawn_player(client_id, customization.clone()) {
+                self.queued_joins.insert(client_id, customization);
+            }
         }
+    }
 
-        Setup {
-            player_id,
-            model: self.model.clone(),
+    /// The part of the model the client is allowed to see.
+    /// Until resolution starts, only the client's own planned move is included.
+    fn snapshot_for(&self, client_id: ClientId) -> SharedModel {
+        let mut model = self.model.visible_to(client_id);
+        if let Phase::Starting { .. } | Phase::Planning { .. } = model.phase {
+            for player in model.players.values_mut() {
+                player.submitted_move = if player.id == client_id {
+                    self.queued_moves
+                        .get(&client_id)
+                        .cloned()
+                        .unwrap_or_default()
+                } else {
+                    PlayerMove::default()
+                };
+            }
+        }
+        if !matches!(model.phase, Phase::Results { .. }) {
+            // Statistics of the others are revealed on the results screen
+            for player in model
+                .players
+                .values_mut()
+                .chain(model.hidden_players.values_mut())
+            {
+                if player.id != client_id {
+                    player.match_stats = MatchStats::default();
+                }
+            }
+        }
+        model
+    }
+
+    /// Sends every client the part of the model they are allowed to see.
+    fn broadcast(&mut self, message: fn(SharedModel) -> ServerMessage) {
+        let messages: Vec<(ClientId, ServerMessage)> = self
+            .clients
+            .keys()
+            .map(|&client_id| (client_id, message(self.snapshot_for(client_id))))
+            .collect();
+        for (client_id, message) in messages {
+            if let Some(client) = self.clients.get_mut(&client_id) {
+                client.sender.send(message);
+            }
+        }
+    }
+
+    /// Sends every client the resolved move with the events they are allowed to see.
+    fn broadcast_move(&mut self, events: Vec<GameEvent>) {
+        if events.is_empty() {
+            return;
+        }
+        let messages: Vec<(ClientId, ServerMessage)> = self
+            .clients
+            .keys()
+            .map(|&client_id| {
+                let events = self.model.events_visible_to(client_id, &events);
+                (
+                    client_id,
+                    ServerMessage::ResolveMove(self.snapshot_for(client_id), events),
+                )
+            })
+            .collect();
+        for (client_id, message) in messages {
+            if let Some(client) = self.clients.get_mut(&client_id) {
+                client.sender.send(message);
+            }
         }
     }
 
     pub fn player_disconnect(&mut self, player_id: ClientId) {
         self.model.players.remove(&player_id);
+        self.queued_moves.remove(&player_id);
+        self.queued_joins.remove(&player_id);
         if self.model.players.is_empty() {
+            self.spawn_queued_players();
             self.model.new_game();
         }
 
-        for client in self.clients.values_mut() {
-            client.sender.send(ServerMessage::Sync(self.model.clone()));
-        }
+        self.broadcast(ServerMessage::Sync);
     }
 
     pub fn player_spectate(&mut self, client_id: ClientId) {
         self.model.players.remove(&client_id);
+        self.queued_moves.remove(&client_id);
+        self.queued_joins.remove(&client_id);
         if self.model.players.is_empty() {
+            self.spawn_queued_players();
             self.model.new_game();
         }
-        if let Some(client) = self.clients.get_mut(&client_id) {
-            client.sender.send(ServerMessage::Sync(self.model.clone()));
-        }
+
+        self.broadcast(ServerMessage::Sync);
     }
 
     pub fn tick(&mut self) {
@@ -90,17 +257,15 @@ impl ServerState {
                 self.model.new_game();
             }
         } else {
+            let mut move_events = Vec::new();
             for event in self.model.update(delta_time) {
                 match event {
                     GameEvent::StartGame => {
                         self.model.start_game();
-                        for client in self.clients.values_mut() {
-                            client
-                                .sender
-                                .send(ServerMessage::StartResolution(self.model.clone()));
-                        }
+                        self.broadcast(ServerMessage::StartResolution);
                     }
                     GameEvent::StartResolution => {
+                        // Moves are revealed to everyone only now
                         for player in self.model.players.values_mut() {
                             player.submitted_move = self
                                 .queued_moves
@@ -109,13 +274,10 @@ impl ServerState {
                                 .unwrap_or_default();
                         }
                         self.model.start_resolution();
-                        for client in self.clients.values_mut() {
-                            client
-                                .sender
-                                .send(ServerMessage::StartResolution(self.model.clone()));
-                        }
+                        self.broadcast(ServerMessage::StartResolution);
                     }
                     GameEvent::FinishResolution => {
+                        self.broadcast_move(std::mem::take(&mut move_events));
                         self.model.finish_resolution();
 
                         // Spawn mushrooms
@@ -124,29 +286,38 @@ impl ServerState {
                         for _ in mushrooms..target {
                             self.model.spawn_mushroom();
                         }
+                        self.model.roll_environment_event();
 
-                        for client in self.clients.values_mut() {
-                            client
-                                .sender
-                                .send(ServerMessage::FinishResolution(self.model.clone()));
-                        }
+                        self.broadcast(ServerMessage::FinishResolution);
                     }
                     GameEvent::ResultsOver => {
+                        self.spawn_queued_players();
                         self.model.new_game();
-                        for client in self.clients.values_mut() {
-                            client
-                                .sender
-                                .send(ServerMessage::StartResolution(self.model.clone()));
-                        }
+                        self.broadcast(ServerMessage::StartResolution);
                     }
-                    _ => {}
+                    event => move_events.push(event),
                 }
             }
+            self.broadcast_move(move_events);
         }
     }
 
     pub fn handle_message(&mut self, client_id: ClientId, message: ClientMessage) {
+        if !matches!(message, ClientMessage::QueryStatus) {
+            self.client_join(client_id);
+        }
+
         match message {
+            ClientMessage::QueryStatus => {
+                let status = self.status();
+                if let Some(client) = self
+                    .pending
+                    .get_mut(&client_id)
+                    .or_else(|| self.clients.get_mut(&client_id))
+                {
+                    client.sender.send(ServerMessage::Status(status));
+                }
+            }
             ClientMessage::Pong => {
                 let client = self
                     .clients
@@ -157,10 +328,12 @@ impl ServerState {
                 // ));
                 client.sender.send(ServerMessage::Ping);
             }
-            ClientMessage::SetCustomization(mut customization) => {
+            ClientMessage::SetCustomization(customization) => {
                 if let Some(player) = self.model.players.get_mut(&client_id) {
-                    customization.name = rustrict::Censor::from_str(&customization.name).collect();
-                    player.customization = customization;
+                    player.set_customization(
+                        sanitize_customization(customization),
+                        &self.model.rules,
+                    );
 
                     for client in self.clients.values_mut() {
                         client.sender.send(ServerMessage::PlayerCustomization(
@@ -168,14 +341,28 @@ impl ServerState {
                             player.customization.clone(),
                         ));
                     }
+                } else if let Some(queued) = self.queued_joins.get_mut(&client_id) {
+                    *queued = sanitize_customization(customization);
                 }
             }
             ClientMessage::Spectate => {
                 self.player_spectate(client_id);
             }
+            ClientMessage::Join(customization) => {
+                self.player_join(client_id, customization);
+            }
             ClientMessage::SubmitMove(mov) => {
                 self.queued_moves.insert(client_id, mov);
             }
         }
     }
 }
+
+/// Censors the name and replaces an invalid loadout.
+fn sanitize_customization(mut customization: PlayerCustomization) -> PlayerCustomization {
+    customization.name = rustrict::Censor::from_str(&customization.name).collect();
+    if !customization.loadout.is_valid() {
+        customization.loadout = Loadout::default();
+    }
+    customization
+}
@@ -0,0 +1,505 @@
+use crate::{
+    assets::Assets,
+    controls::{self, Action, Controls, Gamepads},
+    model::PlayerCustomization,
+    ui::{UiContext, WidgetSfxConfig, WidgetState},
+};
+
+use std::cell::RefCell;
+
+use geng::prelude::*;
+use geng_utils::conversions::*;
+
+/// Key the settings are stored under locally.
+const SETTINGS_KEY: &str = "down_to_trail_settings";
+/// Key the profile is stored under locally.
+const PROFILE_KEY: &str = "down_to_trail_profile";
+
+const VOLUME_STEP: f32 = 0.1;
+const CRT_STEP: f32 = 0.25;
+const MAX_CRT_STRENGTH: f32 = 2.0;
+const UI_SCALE_STEP: f32 = 0.25;
+const MIN_UI_SCALE: f32 = 0.5;
+const MAX_UI_SCALE: f32 = 2.0;
+
+/// Player preferences, saved locally between sessions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub master_volume: f32,
+    pub sfx_volume: f32,
+    /// Strength of the CRT screen effect, `0` turns it off.
+    pub crt_strength: f32,
+    pub ui_scale: f32,
+    pub controls: Controls,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            master_volume: 1.0,
+            sfx_volume: 0.5,
+            crt_strength: 1.0,
+            ui_scale: 1.0,
+            controls: Controls::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Loads the saved settings, or the defaults if nothing has been saved yet.
+    pub fn load() -> Self {
+        preferences::load(SETTINGS_KEY).unwrap_or_default()
+    }
+
+    /// Saves the settings to a file on native and to the local storage on web.
+    pub fn save(&self) {
+        preferences::save(SETTINGS_KEY, self);
+    }
+
+    /// The volume to play sound effects at.
+    pub fn volume(&self) -> f32 {
+        self.master_volume * self.sfx_volume
+    }
+}
+
+/// Player choices remembered between sessions.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// The customization used to join the last game.
+    pub customization: Option<PlayerCustomization>,
+    /// The last server address given with `--connect`.
+    pub connect: Option<String>,
+    /// Server addresses saved in the server list.
+    pub favorites: Vec<String>,
+}
+
+impl Profile {
+    pub fn load() -> Self {
+        preferences::load(PROFILE_KEY).unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        preferences::save(PROFILE_KEY, self);
+    }
+}
+
+pub struct SettingsMenu {
+    geng: Geng,
+    assets: Rc<Assets>,
+    settings: Rc<RefCell<Settings>>,
+    transition: Option<geng::state::Transition>,
+    unit_quad: ugli::VertexBuffer<draw2d::TexturedVertex>,
+    ui_context: UiContext,
+    ui: SettingsUi,
+    framebuffer_size: vec2<usize>,
+    post_texture: ugli::Texture,
+    time: f32,
+    active_touch: Option<u64>,
+    /// The action waiting for a key or button press to be bound to.
+    rebinding: Option<Action>,
+    gamepads: Gamepads,
+}
+
+pub struct SettingsUi {
+    pub master_volume: OptionWidget,
+    pub sfx_volume: OptionWidget,
+    pub crt_strength: OptionWidget,
+    pub ui_scale: OptionWidget,
+    pub bindings: Vec<(Action, WidgetState)>,
+    pub reset: WidgetState,
+    pub back: WidgetState,
+}
+
+/// A value adjusted by the previous and next buttons around it.
+pub struct OptionWidget {
+    pub prev: WidgetState,
+    pub text: WidgetState,
+    pub next: WidgetState,
+}
+
+impl SettingsMenu {
+    pub fn new(geng: &Geng, assets: &Rc<Assets>, settings: &Rc<RefCell<Settings>>) -> Self {
+        Self {
+            geng: geng.clone(),
+            assets: assets.clone(),
+            settings: settings.clone(),
+            transition: None,
+            unit_quad: geng_utils::geometry::unit_quad_geometry(geng.ugli()),
+            ui_context: UiContext::new(geng, assets),
+            ui: SettingsUi::new(geng, assets),
+            framebuffer_size: vec2(1, 1),
+            post_texture: geng_utils::texture::new_texture(geng.ugli(), vec2(1, 1)),
+            time: 0.0,
+            active_touch: None,
+            rebinding: None,
+            gamepads: Gamepads::default(),
+        }
+    }
+}
+
+impl geng::State for SettingsMenu {
+    fn transition(&mut self) -> Option<geng::state::Transition> {
+        self.transition.take()
+    }
+
+    fn update(&mut self, delta_time: f64) {
+        self.time += delta_time as f32;
+        self.ui_context.apply_settings(&self.settings.borrow());
+        self.ui_context
+            .update(delta_time as f32, self.active_touch.is_some());
+        self.ui.update(&mut self.ui_context, self.framebuffer_size);
+
+        let mut settings = self.settings.borrow_mut();
+        let settings = &mut *settings;
+        let mut changed = false;
+        changed |= self
+            .ui
+            .master_volume
+            .step(&mut settings.master_volume, VOLUME_STEP, 0.0, 1.0);
+        changed |= self
+            .ui
+            .sfx_volume
+            .step(&mut settings.sfx_volume, VOLUME_STEP, 0.0, 1.0);
+        changed |=
+            self.ui
+                .crt_strength
+                .step(&mut settings.crt_strength, CRT_STEP, 0.0, MAX_CRT_STRENGTH);
+        changed |= self.ui.ui_scale.step(
+            &mut settings.ui_scale,
+            UI_SCALE_STEP,
+            MIN_UI_SCALE,
+            MAX_UI_SCALE,
+        );
+
+        for (action, widget) in &self.ui.bindings {
+            if widget.mouse_left.clicked {
+                self.rebinding = Some(*action);
+            }
+        }
+        if let Some(&button) = self.gamepads.pressed().first()
+            && let Some(action) = self.rebinding.take()
+        {
+            settings.controls.rebind_gamepad(action, button);
+            changed = true;
+        }
+
+        if self.ui.reset.mouse_left.clicked {
+            *settings = Settings::default();
+            self.rebinding = None;
+            changed = true;
+        }
+        if changed {
+            settings.save();
+        }
+
+        if self.ui.back.mouse_left.clicked {
+            self.transition = Some(geng::state::Transition::Pop);
+        }
+    }
+
+    fn handle_event(&mut self, event: geng::Event) {
+        match event {
+            geng::Event::KeyPress { key } => {
+                if let Some(action) = self.rebinding.take() {
+                    // Escape cancels the rebinding
+                    if key != geng::Key::Escape {
+                        let mut settings = self.settings.borrow_mut();
+                        settings.controls.rebind(action, key);
+                        settings.save();
+                    }
+                } else if key == geng::Key::Escape {
+                    self.transition = Some(geng::state::Transition::Pop);
+                }
+            }
+            geng::Event::CursorMove { position } => {
+                self.ui_context.cursor.cursor_move(position.as_f32());
+            }
+            geng::Event::TouchStart(touch) if self.active_touch.is_none() => {
+                self.active_touch = Some(touch.id);
+                self.ui_context.cursor.cursor_move(touch.position.as_f32());
+            }
+            geng::Event::TouchMove(touch) if Some(touch.id) == self.active_touch => {
+                self.ui_context.cursor.cursor_move(touch.position.as_f32());
+            }
+            geng::Event::TouchEnd(touch) if Some(touch.id) == self.active_touch => {
+                self.ui_context.cursor.cursor_move(touch.position.as_f32());
+                self.active_touch = None;
+            }
+            _ => (),
+        }
+    }
+
+    fn draw(&mut self, final_buffer: &mut ugli::Framebuffer) {
+        self.framebuffer_size = final_buffer.size();
+        geng_utils::texture::update_texture_size(
+            &mut self.post_texture,
+            final_buffer.size(),
+            self.geng.ugli(),
+        );
+        let framebuffer =
+            &mut geng_utils::texture::attach_texture(&mut self.post_texture, self.geng.ugli());
+        ugli::clear(
+            framebuffer,
+            Some(Rgba::try_from("#1A151F").unwrap()),
+            None,
+            None,
+        );
+
+        let settings = self.settings.borrow();
+        let sprites = &self.assets.sprites;
+        let text_color = |state: &WidgetState| {
+            if state.hovered {
+                Rgba::try_from("#5590B4").unwrap()
+            } else {
+                Rgba::try_from("#B4A091").unwrap()
+            }
+        };
+        let draw_text = |framebuffer: &mut ugli::Framebuffer,
+                         text: String,
+                         color: Rgba<f32>,
+                         position: Aabb2<f32>| {
+            self.geng.draw2d().draw2d(
+                framebuffer,
+                &geng::PixelPerfectCamera,
+                &draw2d::Text::unit(self.assets.font.clone(), text, color).fit_into(position),
+            );
+        };
+        let draw_option =
+            |framebuffer: &mut ugli::Framebuffer, option: &OptionWidget, text: String| {
+                for (state, normal, hover, press) in [
+                    (
+                        &option.prev,
+                        &sprites.button_prev,
+                        &sprites.button_prev_hover,
+                        &sprites.button_prev_press,
+                    ),
+                    (
+                        &option.next,
+                        &sprites.button_next,
+                        &sprites.button_next_hover,
+                        &sprites.button_next_press,
+                    ),
+                ] {
+                    let texture = if state.mouse_left.pressed.is_some() {
+                        press
+                    } else if state.hovered {
+                        hover
+                    } else {
+                        normal
+                    };
+                    geng_utils::texture::DrawTexture::new(texture)
+                        .fit(state.position, vec2(0.5, 0.5))
+                        .draw(&geng::PixelPerfectCamera, &self.geng, framebuffer);
+                }
+                draw_text(
+                    framebuffer,
+                    text,
+                    Rgba::try_from("#B4A091").unwrap(),
+                    option.text.position,
+                );
+            };
+
+        draw_option(
+            framebuffer,
+            &self.ui.master_volume,
+            format!("Master volume {:.0}%", settings.master_volume * 100.0),
+        );
+        draw_option(
+            framebuffer,
+            &self.ui.sfx_volume,
+            format!("Sound volume {:.0}%", settings.sfx_volume * 100.0),
+        );
+        draw_option(
+            framebuffer,
+            &self.ui.crt_strength,
+            if settings.crt_strength > 0.0 {
+                format!("CRT effect {:.0}%", settings.crt_strength * 100.0)
+            } else {
+                "CRT effect off".to_owned()
+            },
+        );
+        draw_option(
+            framebuffer,
+            &self.ui.ui_scale,
+            format!("UI scale {:.0}%", settings.ui_scale * 100.0),
+        );
+
+        for (action, widget) in &self.ui.bindings {
+            let (keys, color) = if self.rebinding == Some(*action) {
+                (
+                    "press a key or button..".to_owned(),
+                    Rgba::try_from("#B03B59").unwrap(),
+                )
+            } else {
+                let keys = settings
+                    .controls
+                    .keys(*action)
+                    .iter()
+                    .map(|&key| controls::key_name(key))
+                    .chain(
+                        settings
+                            .controls
+                            .gamepad_buttons(*action)
+                            .iter()
+                            .map(|button| button.name().to_owned()),
+                    )
+                    .collect::<Vec<_>>();
+                let keys = if keys.is_empty() {
+                    "none".to_owned()
+                } else {
+                    keys.join(", ")
+                };
+                (keys, text_color(widget))
+            };
+            draw_text(
+                framebuffer,
+                format!("{}: {}", action.name(), keys),
+                color,
+                widget.position,
+            );
+        }
+
+        draw_text(
+            framebuffer,
+            "Reset".to_owned(),
+            text_color(&self.ui.reset),
+            self.ui.reset.position,
+        );
+        draw_text(
+            framebuffer,
+            "Back".to_owned(),
+            text_color(&self.ui.back),
+            self.ui.back.position,
+        );
+
+        self.ui_context.frame_end();
+
+        crate::render::draw_crt(
+            &self.geng,
+            &self.assets,
+            &self.unit_quad,
+            &self.post_texture,
+            settings.crt_strength,
+            self.time,
+            final_buffer,
+        );
+    }
+}
+
+impl SettingsUi {
+    pub fn new(_geng: &Geng, _assets: &Rc<Assets>) -> Self {
+        Self {
+            master_volume: OptionWidget::new(),
+            sfx_volume: OptionWidget::new(),
+            crt_strength: OptionWidget::new(),
+            ui_scale: OptionWidget::new(),
+            bindings: Action::all()
+                .into_iter()
+                .map(|action| {
+                    (
+                        action,
+                        WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+                    )
+                })
+                .collect(),
+            reset: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+            back: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+        }
+    }
+
+    pub fn update(&mut self, context: &mut UiContext, framebuffer_size: vec2<usize>) {
+        let screen = Aabb2::ZERO.extend_positive(framebuffer_size.as_f32());
+        context.screen = screen;
+
+        // Rows: the options, the bindings and the buttons, with a gap between the groups
+        let rows = 4 + self.bindings.len() + 1 + 2;
+        let row_height =
+            (screen.height() * 0.05 * context.scale).min(screen.height() * 0.9 / rows as f32);
+        let width = row_height * 16.0;
+        let mut row = Aabb2::point(screen.center() + vec2(0.0, row_height * rows as f32 / 2.0))
+            .extend_symmetric(vec2(width / 2.0, 0.0))
+            .extend_down(row_height);
+
+        for option in [
+            &mut self.master_volume,
+            &mut self.sfx_volume,
+            &mut self.crt_strength,
+            &mut self.ui_scale,
+        ] {
+            option.update(row, context);
+            row = row.translate(vec2(0.0, -row_height));
+        }
+        row = row.translate(vec2(0.0, -row_height));
+
+        for (_, widget) in &mut self.bindings {
+            widget.update(row.extend_uniform(-row_height * 0.15), context);
+            row = row.translate(vec2(0.0, -row_height));
+        }
+        row = row.translate(vec2(0.0, -row_height));
+
+        let button_size = vec2(width * 0.25, row_height);
+        self.reset.update(
+            Aabb2::point(row.center() - vec2(width * 0.2, 0.0))
+                .extend_symmetric(button_size / 2.0)
+                .extend_uniform(-row_height * 0.1),
+            context,
+        );
+        self.back.update(
+            Aabb2::point(row.center() + vec2(width * 0.2, 0.0))
+                .extend_symmetric(button_size / 2.0)
+                .extend_uniform(-row_height * 0.1),
+            context,
+        );
+    }
+}
+
+impl Default for OptionWidget {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OptionWidget {
+    pub fn new() -> Self {
+        Self {
+            prev: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+            text: WidgetState::new(),
+            next: WidgetState::new().with_sfx(WidgetSfxConfig::hover_left()),
+        }
+    }
+
+    pub fn update(&mut self, row: Aabb2<f32>, context: &UiContext) {
+        let button_size = vec2(3.0, 4.0) / 4.0 * row.height() * 0.6;
+        self.prev.update(
+            Aabb2::point(row.center() - vec2(row.width() * 0.4, 0.0))
+                .extend_symmetric(button_size / 2.0),
+            context,
+        );
+        self.next.update(
+            Aabb2::point(row.center() + vec2(row.width() * 0.4, 0.0))
+                .extend_symmetric(button_size / 2.0),
+            context,
+        );
+        self.text.update(
+            Aabb2::point(row.center())
+                .extend_symmetric(vec2(row.width() * 0.35, row.height() * 0.35)),
+            context,
+        );
+    }
+
+    /// Steps the value by the buttons clicked this frame.
+    /// Returns whether the value has changed.
+    pub fn step(&self, value: &mut f32, step: f32, min: f32, max: f32) -> bool {
+        let delta = self.next.mouse_left.clicked as i32 - self.prev.mouse_left.clicked as i32;
+        if delta == 0 {
+            return false;
+        }
+        // Snap to the step to avoid accumulating float errors
+        let new_value = (((*value / step).round() + delta as f32) * step).clamp(min, max);
+        let changed = new_value != *value;
+        *value = new_value;
+        changed
+    }
+}
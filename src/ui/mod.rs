@@ -1,4 +1,4 @@
-use crate::assets::Assets;
+use crate::{assets::Assets, settings::Settings};
 
 use geng::prelude::*;
 
@@ -16,6 +16,10 @@ pub struct UiContext {
     pub real_time: f32,
     pub delta_time: f32,
     pub screen: Aabb2<f32>,
+    /// Volume of the widget sound effects.
+    pub volume: f32,
+    /// Multiplier for the size of the layout.
+    pub scale: f32,
 }
 
 impl UiContext {
@@ -30,9 +34,17 @@ impl UiContext {
             screen: Aabb2::ZERO.extend_positive(vec2(1.0, 1.0)),
             real_time: 0.0,
             delta_time: 0.1,
+            volume: 0.5,
+            scale: 1.0,
         }
     }
 
+    /// Applies the player's volume and scale preferences.
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        self.volume = settings.volume();
+        self.scale = settings.ui_scale;
+    }
+
     /// Should be called before layout.
     /// Updates input values.
     // TODO: use window from context
@@ -187,11 +199,11 @@ impl WidgetState {
 
             if self.mouse_left.clicked && self.sfx_config.left_click {
                 let mut sfx = context.assets.sounds.click.play();
-                sfx.set_volume(0.5);
+                sfx.set_volume(context.volume);
             }
             if !was_hovered && self.hovered && self.sfx_config.hover {
                 let mut sfx = context.assets.sounds.hover.play();
-                sfx.set_volume(0.5);
+                sfx.set_volume(context.volume);
             }
         } else {
             self.mouse_left.just_released = self.mouse_left.pressed.is_some();